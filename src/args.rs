@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Context as _, Result};
+
+// Command line: `workflows [options] <config>`, where `-` reads the config
+// from stdin.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub config: String,
+    // Prints what each step would run instead of running it.
+    pub dry_run: bool,
+    // Only loads and validates the config, reporting every problem.
+    pub check: bool,
+    // A dotenv file whose variables join the environment. Nothing is loaded
+    // without it, not even a `.env` in the working directory.
+    pub env_file: Option<String>,
+    // Writes the final outputs as JSON to this file, or stdout for `-`.
+    pub output: Option<String>,
+    // `--set name=value` pairs, which replace or add to the config's vars.
+    pub set: Vec<(String, String)>,
+    // Runs only the named step, or the steps from the named one on.
+    pub only: Option<String>,
+    pub from: Option<String>,
+    // Prints the workflow types with their parameters and outputs instead of
+    // running a config, which is then optional.
+    pub list: bool,
+    // Each -v logs more: steps and their parameters, then filled templates
+    // and outputs.
+    pub verbose: u8,
+}
+
+impl Args {
+    // Takes the arguments after the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args> {
+        let mut parsed = Args::default();
+        let mut config = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("Missing value for {}.", arg))
+            };
+            match &arg[..] {
+                "--dry-run" => parsed.dry_run = true,
+                "--list" => parsed.list = true,
+                "--check" => parsed.check = true,
+                "-v" | "--verbose" => parsed.verbose += 1,
+                "-vv" => parsed.verbose += 2,
+                "--env-file" => parsed.env_file = Some(value()?),
+                "--output" => parsed.output = Some(value()?),
+                "--set" => {
+                    let pair = value()?;
+                    let (name, value) = pair
+                        .split_once('=')
+                        .with_context(|| format!("Invalid --set {}, expected name=value.", pair))?;
+                    parsed
+                        .set
+                        .push((name.trim().to_string(), value.to_string()));
+                }
+                "--only" => parsed.only = Some(value()?),
+                "--from" => parsed.from = Some(value()?),
+                "-" if config.is_none() => config = Some(arg),
+                option if option.starts_with('-') => {
+                    return Err(anyhow!("Unknown option {}.", option))
+                }
+                _ if config.is_none() => config = Some(arg),
+                _ => return Err(anyhow!("Unexpected argument {}.", arg)),
+            }
+        }
+        if parsed.only.is_some() && parsed.from.is_some() {
+            return Err(anyhow!("--only and --from cannot be combined."));
+        }
+        parsed.config = match config {
+            Some(config) => config,
+            None if parsed.list => String::new(),
+            None => return Err(anyhow!("No configuration is provided.")),
+        };
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(&["config.yml"]).unwrap(),
+            Args {
+                config: "config.yml".to_string(),
+                ..Args::default()
+            }
+        );
+        assert!(parse(&["--dry-run", "config.yml"]).unwrap().dry_run);
+        assert!(parse(&["--list"]).unwrap().list);
+        assert!(parse(&["--check", "config.yml"]).unwrap().check);
+        assert_eq!(parse(&["-v", "-"]).unwrap().config, "-");
+        assert_eq!(parse(&["-v", "config.yml"]).unwrap().verbose, 1);
+        assert_eq!(parse(&["config.yml", "-vv"]).unwrap().verbose, 2);
+        assert_eq!(
+            parse(&[]).unwrap_err().to_string(),
+            "No configuration is provided."
+        );
+        assert_eq!(
+            parse(&["config.yml", "--force"]).unwrap_err().to_string(),
+            "Unknown option --force."
+        );
+        assert_eq!(
+            parse(&["--env-file", "prod.env", "config.yml"])
+                .unwrap()
+                .env_file,
+            Some("prod.env".to_string())
+        );
+        assert_eq!(
+            parse(&["--output", "-", "config.yml"]).unwrap().output,
+            Some("-".to_string())
+        );
+        assert_eq!(
+            parse(&["config.yml", "--env-file"])
+                .unwrap_err()
+                .to_string(),
+            "Missing value for --env-file."
+        );
+        assert_eq!(
+            parse(&["a.yml", "b.yml"]).unwrap_err().to_string(),
+            "Unexpected argument b.yml."
+        );
+        assert_eq!(
+            parse(&["config.yml", "--set", "env=prod", "--set", "query=a=b"])
+                .unwrap()
+                .set,
+            vec![
+                ("env".to_string(), "prod".to_string()),
+                ("query".to_string(), "a=b".to_string())
+            ]
+        );
+        assert_eq!(
+            parse(&["config.yml", "--set", "prod"])
+                .unwrap_err()
+                .to_string(),
+            "Invalid --set prod, expected name=value."
+        );
+        assert_eq!(
+            parse(&["--from", "build", "config.yml"]).unwrap().from,
+            Some("build".to_string())
+        );
+        assert_eq!(
+            parse(&["--only", "a", "--from", "b", "config.yml"])
+                .unwrap_err()
+                .to_string(),
+            "--only and --from cannot be combined."
+        );
+    }
+}
@@ -2,7 +2,7 @@ use crate::{Context, Input, Inputs, Outputs, Workflow};
 use anyhow::Result;
 use atom_syndication::Feed;
 use chrono::{Duration, Local};
-use std::{io::BufReader};
+use std::io::BufReader;
 
 pub struct Atom {}
 
@@ -11,6 +11,7 @@ impl Atom {
     const TEXT: &'static str = "text";
     const SCHEDULE_IN_SECS: &'static str = "schedule_in_secs";
     const PARAMS: [&'static str; 2] = [Atom::TEXT, Atom::SCHEDULE_IN_SECS];
+    const REQUIRED: [&'static str; 1] = [Atom::TEXT];
 
     // Output
     const TITLE: &'static str = "title";
@@ -19,18 +20,15 @@ impl Atom {
 }
 
 impl Workflow for Atom {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let text = input.parameter(Atom::TEXT);
         let after = input
             .parameter(Atom::SCHEDULE_IN_SECS)
             .parse()
             .map(|secs| Local::now() - Duration::seconds(secs));
 
-        let next = match context.next() {
-            Some(next) => next,
-            None => return Ok(()),
-        };
         let feed = Feed::read_from(BufReader::new(text.as_bytes()))?;
+        let mut outputs = Vec::new();
         for entry in feed.entries() {
             if let Ok(after) = after {
                 if entry.updated() < &after {
@@ -38,9 +36,9 @@ impl Workflow for Atom {
                 }
             }
             let mut output = Outputs::new();
-            output.insert(Atom::TITLE, entry.title().to_string());
+            output.insert(Atom::TITLE.to_string(), entry.title().to_string());
             output.insert(
-                Atom::LINK,
+                Atom::LINK.to_string(),
                 entry
                     .links()
                     .iter()
@@ -48,16 +46,19 @@ impl Workflow for Atom {
                     .collect::<Vec<_>>()
                     .join(","),
             );
-            next.execute(context, output)?;
+            outputs.push(output);
         }
 
-        Ok(())
+        Ok(outputs)
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Atom::PARAMS;
+        &Atom::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Atom::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Atom::OUTPUT;
+        &Atom::OUTPUT
     }
 }
@@ -1,51 +1,439 @@
-use crate::{Context, Input, Inputs, Workflow};
-use anyhow::Result;
+use crate::{
+    util::{pairs, split_args},
+    Context, Input, Inputs, Outputs, Workflow,
+};
+use anyhow::{anyhow, Result};
 use std::{
     collections::HashMap,
-    process::{Command as StdCommand, Stdio},
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command as StdCommand, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 pub struct Command {}
 
 impl Command {
     // Input
+    // Filled like any template, so a brace before a letter, as in `${HOME}`
+    // or awk's `{print $1}`, must be written `\{`. The same goes for args.
     const PROGRAM: &'static str = "program";
     const DAEMON: &'static str = "daemon";
     const INHERIT_IO: &'static str = "inherit_io";
-    const PARAMS: [&'static str; 3] = [Command::PROGRAM, Command::DAEMON, Command::INHERIT_IO];
+    // Split on whitespace, quotes group words into one argument.
+    const ARGS: &'static str = "args";
+    const WORKING_DIR: &'static str = "working_dir";
+    // One `KEY=VALUE` pair per line, added on top of the inherited environment.
+    const ENV: &'static str = "env";
+    // Starts from an empty environment instead of inheriting ours.
+    const CLEAR_ENV: &'static str = "clear_env";
+    // Written to the program's stdin, which is then closed.
+    const STDIN: &'static str = "stdin";
+    // Kills the program if it is still running after this many seconds.
+    const TIMEOUT_SECS: &'static str = "timeout_secs";
+    // Pipes stdout and stderr into outputs instead of discarding them.
+    const CAPTURE_OUTPUT: &'static str = "capture_output";
+    // Runs program as a script through `sh -c` (`cmd /C` on Windows), with args
+    // as its positional parameters. Anything templated into program is then
+    // interpreted by the shell, so only enable this for trusted values.
+    const SHELL: &'static str = "shell";
+    // Fails the step on a non-zero exit, ignored for daemons.
+    const CHECK: &'static str = "check";
+    const PARAMS: [&'static str; 12] = [
+        Command::PROGRAM,
+        Command::DAEMON,
+        Command::INHERIT_IO,
+        Command::ARGS,
+        Command::WORKING_DIR,
+        Command::ENV,
+        Command::CLEAR_ENV,
+        Command::STDIN,
+        Command::TIMEOUT_SECS,
+        Command::CAPTURE_OUTPUT,
+        Command::SHELL,
+        Command::CHECK,
+    ];
+    const REQUIRED: [&'static str; 1] = [Command::PROGRAM];
+
+    // Output
+    const STDOUT: &'static str = "stdout";
+    const STDERR: &'static str = "stderr";
+    // `-1` when killed by a signal, `spawned` for daemons.
+    const EXIT_CODE: &'static str = "exit_code";
+    const OUTPUT: [&'static str; 3] = [Command::STDOUT, Command::STDERR, Command::EXIT_CODE];
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    #[cfg(unix)]
+    fn shell(script: &str) -> StdCommand {
+        let mut command = StdCommand::new("sh");
+        // The trailing `sh` becomes $0 so args start at $1.
+        command.arg("-c").arg(script).arg("sh");
+        command
+    }
+
+    #[cfg(windows)]
+    fn shell(script: &str) -> StdCommand {
+        let mut command = StdCommand::new("cmd");
+        command.arg("/C").arg(script);
+        command
+    }
 
-    const OUTPUT: [&'static str; 0] = [];
+    // Reads a pipe on its own thread so a full pipe cannot block the child.
+    fn capture<R>(pipe: Option<R>) -> JoinHandle<Result<String>>
+    where
+        R: Read + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut bytes)?;
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            let text = text
+                .strip_suffix("\r\n")
+                .or_else(|| text.strip_suffix('\n'))
+                .unwrap_or(&text);
+            Ok(text.to_string())
+        })
+    }
+
+    // Writes stdin on its own thread too, so a child that never reads it
+    // cannot block the timeout. A child exiting before reading all of it is
+    // not an error.
+    fn feed<W>(pipe: Option<W>, stdin: String) -> JoinHandle<io::Result<()>>
+    where
+        W: Write + Send + 'static,
+    {
+        thread::spawn(move || match pipe {
+            Some(mut pipe) => match pipe.write_all(stdin.as_bytes()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            },
+            None => Ok(()),
+        })
+    }
+
+    fn join(handle: JoinHandle<Result<String>>) -> Result<String> {
+        handle
+            .join()
+            .map_err(|_| anyhow!("Unable to capture command output."))?
+    }
+
+    // std::process has no timed wait, so poll until the deadline passes.
+    fn wait(handle: &mut Child, timeout_secs: Option<u64>) -> Result<ExitStatus> {
+        let timeout = match timeout_secs {
+            Some(secs) => Duration::from_secs(secs),
+            None => return Ok(handle.wait()?),
+        };
+        let start = Instant::now();
+        loop {
+            if let Some(status) = handle.try_wait()? {
+                return Ok(status);
+            }
+            if start.elapsed() >= timeout {
+                handle.kill()?;
+                handle.wait()?;
+                return Err(anyhow!(
+                    "Command timed out after {} seconds.",
+                    timeout.as_secs()
+                ));
+            }
+            thread::sleep(Command::POLL_INTERVAL);
+        }
+    }
 }
 
 impl Workflow for Command {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let program = input.parameter(Command::PROGRAM);
-        let daemon: bool = input.parameter(Command::DAEMON).parse().unwrap_or(false);
-        let inherit_io: bool = input
-            .parameter(Command::INHERIT_IO)
-            .parse()
-            .unwrap_or(false);
-
-        let mut command = StdCommand::new(program);
-        if !inherit_io {
+        let daemon = input.flag(Command::DAEMON, false)?;
+        let inherit_io = input.flag(Command::INHERIT_IO, false)?;
+        let shell = input.flag(Command::SHELL, false)?;
+        let check = input.flag(Command::CHECK, false)?;
+        let args = split_args(input.parameter(Command::ARGS))?;
+        let working_dir = input.parameter(Command::WORKING_DIR);
+        let env = pairs(input.parameter(Command::ENV), '=')?;
+        let clear_env = input.flag(Command::CLEAR_ENV, false)?;
+        let stdin = input.get(Command::STDIN);
+        let timeout_secs = input.parse(Command::TIMEOUT_SECS)?;
+        let capture_output = input.flag(Command::CAPTURE_OUTPUT, false)?;
+        if capture_output && (inherit_io || daemon) {
+            return Err(anyhow!(
+                "Command cannot capture output with inherit_io or daemon."
+            ));
+        }
+        if daemon && stdin.is_some() {
+            return Err(anyhow!("Command cannot write stdin to a daemon."));
+        }
+
+        let mut command = if shell {
+            Command::shell(program)
+        } else {
+            StdCommand::new(program)
+        };
+        command.args(args);
+        if !working_dir.is_empty() {
+            if !Path::new(working_dir).is_dir() {
+                return Err(anyhow!("Working directory {} does not exist.", working_dir));
+            }
+            command.current_dir(working_dir);
+        }
+        if clear_env {
+            command.env_clear();
+        }
+        command.envs(env);
+        if capture_output {
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        } else if !inherit_io {
             command.stdout(Stdio::null());
             command.stderr(Stdio::null());
         }
+        if stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
         let mut handle = command.spawn()?;
-        if !daemon {
-            handle.wait()?;
+        let stdout = Command::capture(handle.stdout.take());
+        let stderr = Command::capture(handle.stderr.take());
+        let stdin = stdin.map(|stdin| Command::feed(handle.stdin.take(), stdin.clone()));
+        let status = if daemon {
+            None
+        } else {
+            Some(Command::wait(&mut handle, timeout_secs)?)
+        };
+        if let Some(stdin) = stdin {
+            stdin
+                .join()
+                .map_err(|_| anyhow!("Unable to write the command's stdin."))??;
+        }
+        let stdout = Command::join(stdout)?;
+        let stderr = Command::join(stderr)?;
+        let exit_code = match status {
+            Some(status) => status.code().unwrap_or(-1).to_string(),
+            None => "spawned".to_string(),
+        };
+        if check && matches!(status, Some(status) if !status.success()) {
+            return Err(anyhow!("Command exited with {}: {}", exit_code, stderr));
         }
 
-        if let Some(next) = context.next() {
-            next.execute(context, HashMap::new())?;
+        let mut result = HashMap::new();
+        result.insert(Command::EXIT_CODE.to_string(), exit_code);
+        if capture_output {
+            result.insert(Command::STDOUT.to_string(), stdout);
+            result.insert(Command::STDERR.to_string(), stderr);
         }
-        Ok(())
+        Ok(vec![result])
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Command::PARAMS;
+        &Command::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Command::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Command::OUTPUT;
+        &Command::OUTPUT
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        let error = run(
+            Command {},
+            &[
+                (Command::PROGRAM, "sleep"),
+                (Command::ARGS, "10"),
+                (Command::TIMEOUT_SECS, "1"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Command timed out after 1 seconds.");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        run(
+            Command {},
+            &[(Command::PROGRAM, "true"), (Command::TIMEOUT_SECS, "5")],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let error = run(
+            Command {},
+            &[
+                (Command::PROGRAM, "echo oops >&2; exit 3"),
+                (Command::SHELL, "true"),
+                (Command::CAPTURE_OUTPUT, "true"),
+                (Command::CHECK, "true"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Command exited with 3: oops");
+
+        run(Command {}, &[(Command::PROGRAM, "false")]).unwrap();
+    }
+
+    #[test]
+    fn test_flags() {
+        let error = run(
+            Command {},
+            &[(Command::PROGRAM, "false"), (Command::CHECK, "yes")],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Command exited with 1: ");
+
+        let error = run(
+            Command {},
+            &[(Command::PROGRAM, "true"), (Command::DAEMON, "ture")],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid daemon ture.");
+    }
+
+    #[test]
+    fn test_stdin() {
+        let outputs = run(
+            Command {},
+            &[
+                (Command::PROGRAM, "cat"),
+                (Command::STDIN, "hello"),
+                (Command::CAPTURE_OUTPUT, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Command::STDOUT], "hello");
+
+        // Far more than a pipe holds, to a child that never reads it.
+        let stdin = "x".repeat(1 << 20);
+        let start = Instant::now();
+        let error = run(
+            Command {},
+            &[
+                (Command::PROGRAM, "sleep"),
+                (Command::ARGS, "5"),
+                (Command::STDIN, &stdin),
+                (Command::TIMEOUT_SECS, "1"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Command timed out after 1 seconds.");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        run(
+            Command {},
+            &[(Command::PROGRAM, "true"), (Command::STDIN, &stdin)],
+        )
+        .unwrap();
+    }
+
+    fn stdout(parameters: &[(&'static str, &str)]) -> String {
+        let mut parameters = parameters.to_vec();
+        parameters.push((Command::CAPTURE_OUTPUT, "true"));
+        run(Command {}, &parameters).unwrap()[0][Command::STDOUT].clone()
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(
+            stdout(&[
+                (Command::PROGRAM, "printf"),
+                (Command::ARGS, r#"%s| "a b" 'c "d"' e\ f"#),
+            ]),
+            r#"a b|c "d"|e f|"#
+        );
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let dir = scratch("command-working-dir").canonicalize().unwrap();
+        assert_eq!(
+            stdout(&[
+                (Command::PROGRAM, "pwd"),
+                (Command::WORKING_DIR, dir.to_str().unwrap()),
+            ]),
+            dir.to_str().unwrap()
+        );
+
+        let error = run(
+            Command {},
+            &[
+                (Command::PROGRAM, "pwd"),
+                (Command::WORKING_DIR, "/no/such/dir"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Working directory /no/such/dir does not exist."
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let script = r#"echo "$FOO|$HOME""#;
+        let home = std::env::var("HOME").unwrap_or_default();
+        assert_eq!(
+            stdout(&[
+                (Command::PROGRAM, script),
+                (Command::SHELL, "true"),
+                (Command::ENV, "FOO=bar"),
+            ]),
+            format!("bar|{}", home)
+        );
+        assert_eq!(
+            stdout(&[
+                (Command::PROGRAM, script),
+                (Command::SHELL, "true"),
+                (Command::ENV, "FOO=bar"),
+                (Command::CLEAR_ENV, "true"),
+            ]),
+            "bar|"
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        assert_eq!(
+            stdout(&[
+                (Command::PROGRAM, r#"echo "$1-$2""#),
+                (Command::SHELL, "true"),
+                (Command::ARGS, "a 'b c'"),
+            ]),
+            "a-b c"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let outputs = run(
+            Command {},
+            &[(Command::PROGRAM, "exit 3"), (Command::SHELL, "true")],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Command::EXIT_CODE], "3");
+        assert!(!outputs[0].contains_key(Command::STDOUT));
+
+        let outputs = run(
+            Command {},
+            &[(Command::PROGRAM, "kill -9 $$"), (Command::SHELL, "true")],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Command::EXIT_CODE], "-1");
+
+        let outputs = run(
+            Command {},
+            &[(Command::PROGRAM, "true"), (Command::DAEMON, "true")],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Command::EXIT_CODE], "spawned");
     }
 }
@@ -0,0 +1,294 @@
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use std::path::{Path, PathBuf};
+use std::{collections::HashMap, fs, process};
+
+pub struct Copy {}
+
+impl Copy {
+    // Input
+    const SOURCE: &'static str = "source";
+    const DESTINATION: &'static str = "destination";
+    // Replaces whatever is at the destination.
+    const OVERWRITE: &'static str = "overwrite";
+    // Removes the source afterwards.
+    const MOVE: &'static str = "move";
+    const PARAMS: [&'static str; 4] =
+        [Copy::SOURCE, Copy::DESTINATION, Copy::OVERWRITE, Copy::MOVE];
+    const REQUIRED: [&'static str; 2] = [Copy::SOURCE, Copy::DESTINATION];
+
+    // Output
+    const OUTPUT: [&'static str; 1] = [Copy::DESTINATION];
+
+    // Directories are copied with everything in them.
+    fn copy(source: &Path, destination: &Path) -> Result<()> {
+        if source.is_dir() {
+            fs::create_dir_all(destination)?;
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                Copy::copy(&entry.path(), &destination.join(entry.file_name()))?;
+            }
+        } else {
+            fs::copy(source, destination)?;
+        }
+        Ok(())
+    }
+
+    fn remove(path: &Path) -> Result<()> {
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    // A hidden path next to path, for staging what replaces it.
+    fn sibling(path: &Path, purpose: &str) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!(".{}.{}-{}", name, purpose, process::id()))
+    }
+
+    // Puts staged in place of path. A file is replaced by a single rename;
+    // otherwise path is set aside first and restored if the swap fails.
+    fn replace(staged: &Path, path: &Path) -> Result<()> {
+        if !staged.is_dir() && !path.is_dir() {
+            fs::rename(staged, path)?;
+            return Ok(());
+        }
+        let aside = Copy::sibling(path, "old");
+        fs::rename(path, &aside)?;
+        if let Err(e) = fs::rename(staged, path) {
+            let _ = fs::rename(&aside, path);
+            return Err(e.into());
+        }
+        Copy::remove(&aside)
+    }
+
+    // Resolves the part of path that exists, so a destination not yet created
+    // can be compared with the source.
+    fn resolve(path: &Path) -> PathBuf {
+        let mut missing = Vec::new();
+        let mut existing = path;
+        loop {
+            if let Ok(resolved) = existing.canonicalize() {
+                return missing
+                    .iter()
+                    .rev()
+                    .fold(resolved, |path, name| path.join(name));
+            }
+            match (existing.file_name(), existing.parent()) {
+                (Some(name), Some(parent)) => {
+                    missing.push(name);
+                    existing = match parent.as_os_str().is_empty() {
+                        true => Path::new("."),
+                        false => parent,
+                    };
+                }
+                _ => return path.to_path_buf(),
+            }
+        }
+    }
+}
+
+impl Workflow for Copy {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let source = input.parameter(Copy::SOURCE);
+        let destination = input.parameter(Copy::DESTINATION);
+        let overwrite = input.flag(Copy::OVERWRITE, false)?;
+        let move_source = input.flag(Copy::MOVE, false)?;
+
+        let (from, to) = (Path::new(source), Path::new(destination));
+        if !from.exists() {
+            return Err(anyhow!("{} does not exist.", source));
+        }
+        if from.is_dir() && Copy::resolve(to).starts_with(from.canonicalize()?) {
+            return Err(anyhow!(
+                "Unable to copy {} into itself at {}.",
+                source,
+                destination
+            ));
+        }
+        let replacing = to.exists();
+        if replacing && !overwrite {
+            return Err(anyhow!(
+                "{} already exists, set overwrite to replace it.",
+                destination
+            ));
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // What is replaced stays until its replacement is complete.
+        let target = match replacing {
+            true => Copy::sibling(to, "new"),
+            false => to.to_path_buf(),
+        };
+        // Renaming fails across filesystems, so fall back to copying.
+        let renamed = move_source && fs::rename(from, &target).is_ok();
+        if !renamed {
+            if let Err(e) = Copy::copy(from, &target) {
+                if target.exists() {
+                    let _ = Copy::remove(&target);
+                }
+                return Err(e)
+                    .with_context(|| format!("Unable to copy {} to {}.", source, destination));
+            }
+        }
+        if replacing {
+            Copy::replace(&target, to)
+                .with_context(|| format!("Unable to replace {}.", destination))?;
+        }
+        if move_source && !renamed {
+            Copy::remove(from)?;
+        }
+
+        let mut result = HashMap::new();
+        result.insert(Copy::DESTINATION.to_string(), destination.to_string());
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Copy::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Copy::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Copy::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+
+    #[test]
+    fn test_copy() {
+        let dir = scratch("copy");
+        fs::create_dir_all(dir.join("release/bin")).unwrap();
+        fs::write(dir.join("release/bin/tool"), "v1").unwrap();
+        let release = dir.join("release");
+        let (source, destination) = (
+            release.to_str().unwrap(),
+            dir.join("opt/tool").to_str().unwrap().to_string(),
+        );
+
+        let outputs = run(
+            Copy {},
+            &[(Copy::SOURCE, source), (Copy::DESTINATION, &destination)],
+        );
+        assert_eq!(outputs.unwrap()[0][Copy::DESTINATION], destination);
+        assert_eq!(
+            fs::read_to_string(dir.join("opt/tool/bin/tool")).unwrap(),
+            "v1"
+        );
+
+        assert_eq!(
+            run(
+                Copy {},
+                &[(Copy::SOURCE, source), (Copy::DESTINATION, &destination)]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!(
+                "{} already exists, set overwrite to replace it.",
+                destination
+            )
+        );
+        fs::write(dir.join("release/bin/tool"), "v2").unwrap();
+        run(
+            Copy {},
+            &[
+                (Copy::SOURCE, source),
+                (Copy::DESTINATION, &destination),
+                (Copy::OVERWRITE, "true"),
+                (Copy::MOVE, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("opt/tool/bin/tool")).unwrap(),
+            "v2"
+        );
+        assert!(!release.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_overwrite() {
+        let dir = scratch("copy-failed");
+        fs::create_dir_all(dir.join("release")).unwrap();
+        fs::write(dir.join("release/tool"), "v2").unwrap();
+        // Copying follows the link and fails halfway through the directory.
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("release/broken")).unwrap();
+        fs::create_dir_all(dir.join("opt")).unwrap();
+        fs::write(dir.join("opt/tool"), "v1").unwrap();
+        let (source, destination) = (dir.join("release"), dir.join("opt"));
+
+        let error = run(
+            Copy {},
+            &[
+                (Copy::SOURCE, source.to_str().unwrap()),
+                (Copy::DESTINATION, destination.to_str().unwrap()),
+                (Copy::OVERWRITE, "true"),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Unable to copy"));
+        assert_eq!(fs::read_to_string(dir.join("opt/tool")).unwrap(), "v1");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_file(dir.join("release/broken")).unwrap();
+        run(
+            Copy {},
+            &[
+                (Copy::SOURCE, source.to_str().unwrap()),
+                (Copy::DESTINATION, destination.to_str().unwrap()),
+                (Copy::OVERWRITE, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("opt/tool")).unwrap(), "v2");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_into_itself() {
+        let dir = scratch("copy-itself");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a/tool"), "v1").unwrap();
+        let (source, destination) = (dir.join("a"), dir.join("a/b/c"));
+        let (source, destination) = (source.to_str().unwrap(), destination.to_str().unwrap());
+
+        for move_source in ["false", "true"] {
+            assert_eq!(
+                run(
+                    Copy {},
+                    &[
+                        (Copy::SOURCE, source),
+                        (Copy::DESTINATION, destination),
+                        (Copy::MOVE, move_source),
+                    ],
+                )
+                .unwrap_err()
+                .to_string(),
+                format!("Unable to copy {} into itself at {}.", source, destination)
+            );
+        }
+        assert!(!dir.join("a/b").exists());
+
+        // A sibling sharing the name's prefix is not inside it.
+        run(
+            Copy {},
+            &[
+                (Copy::SOURCE, source),
+                (Copy::DESTINATION, dir.join("ab").to_str().unwrap()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("ab/tool")).unwrap(), "v1");
+    }
+}
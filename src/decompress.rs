@@ -1,40 +1,817 @@
-use crate::{Context, Input, Inputs, Workflow};
-use anyhow::Result;
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
-use std::{collections::HashMap, fs::File};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+use strum::EnumString;
 use tar::Archive;
+use xz2::read::XzDecoder;
+use zip::read::{read_zipfile_from_stream, ZipFile};
+use zip::ZipArchive;
 
 pub struct Decompress {}
 
+#[derive(Debug, Clone, Copy, EnumString)]
+enum Format {
+    #[strum(serialize = "tar.gz", serialize = "tgz")]
+    TarGz,
+    #[strum(serialize = "tar.bz2", serialize = "tbz2")]
+    TarBz2,
+    #[strum(serialize = "tar.xz", serialize = "txz")]
+    TarXz,
+    #[strum(serialize = "zip")]
+    Zip,
+}
+
+impl Format {
+    const MAGIC: [(&'static [u8], Format); 4] = [
+        (&[0x1f, 0x8b], Format::TarGz),
+        (b"BZh", Format::TarBz2),
+        (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Format::TarXz),
+        (b"PK", Format::Zip),
+    ];
+
+    const EXTENSIONS: [(&'static str, Format); 7] = [
+        (".tar.gz", Format::TarGz),
+        (".tgz", Format::TarGz),
+        (".tar.bz2", Format::TarBz2),
+        (".tbz2", Format::TarBz2),
+        (".tar.xz", Format::TarXz),
+        (".txz", Format::TarXz),
+        (".zip", Format::Zip),
+    ];
+
+    // Sniffs the leading bytes first and only then trusts the extension, so
+    // downloads saved without a suffix still work.
+    fn detect(path: &str) -> Result<Format> {
+        let mut header = Vec::new();
+        File::open(path)
+            .with_context(|| format!("Unable to open {}.", path))?
+            .take(6)
+            .read_to_end(&mut header)?;
+        Format::sniff(&header, path)
+    }
+
+    fn sniff(header: &[u8], name: &str) -> Result<Format> {
+        let lowercase = name.to_lowercase();
+        Format::MAGIC
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, format)| *format)
+            .or_else(|| {
+                Format::EXTENSIONS
+                    .iter()
+                    .find(|(extension, _)| lowercase.ends_with(extension))
+                    .map(|(_, format)| *format)
+            })
+            .ok_or_else(|| anyhow!("Unsupported archive format {}.", name))
+    }
+}
+
+// What to do when an entry would replace an existing file.
+#[derive(Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum Conflict {
+    Skip,
+    Overwrite,
+    Error,
+}
+
+// Where and how entries are written, shared by every format.
+struct Extraction<'a> {
+    destination: &'a Path,
+    // The canonical destination every written path must stay within.
+    root: PathBuf,
+    strip_components: usize,
+    on_conflict: Conflict,
+}
+
+impl Extraction<'_> {
+    fn new<'a>(destination: &'a Path, input: &Inputs) -> Result<Extraction<'a>> {
+        if destination.is_file() {
+            return Err(anyhow!(
+                "Destination {} is a file, not a directory.",
+                destination.display()
+            ));
+        }
+        fs::create_dir_all(destination)
+            .with_context(|| format!("Unable to create {}.", destination.display()))?;
+        Ok(Extraction {
+            destination,
+            root: destination.canonicalize()?,
+            strip_components: input.parse(Decompress::STRIP_COMPONENTS)?.unwrap_or(0),
+            on_conflict: input
+                .parse(Decompress::ON_CONFLICT)?
+                .unwrap_or(Conflict::Overwrite),
+        })
+    }
+
+    fn outputs(&self, extracted: &[PathBuf]) -> Outputs {
+        let top_level_paths: BTreeSet<_> = extracted
+            .iter()
+            .filter_map(|path| path.components().next())
+            .map(|first| first.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let extracted_files: Vec<_> = extracted
+            .iter()
+            .filter(|path| !self.destination.join(path).is_dir())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+
+        let mut result = HashMap::new();
+        result.insert(
+            Decompress::TOP_LEVEL_PATHS.to_string(),
+            top_level_paths.into_iter().collect::<Vec<_>>().join("\n"),
+        );
+        result.insert(
+            Decompress::EXTRACTED_FILES.to_string(),
+            extracted_files.join("\n"),
+        );
+        result
+    }
+
+    // Resolves an entry to its path relative to the destination, or `None` when it
+    // should not be written. Parent directories are created on the way.
+    fn prepare(&self, path: &Path) -> Result<Option<PathBuf>> {
+        let relative = match self.relative(path)? {
+            Some(relative) => relative,
+            None => return Ok(None),
+        };
+        let target = self.destination.join(&relative);
+        if target.is_file() {
+            match self.on_conflict {
+                Conflict::Skip => return Ok(None),
+                Conflict::Overwrite => fs::remove_file(&target)?,
+                Conflict::Error => {
+                    return Err(anyhow!("{} already exists.", target.display()));
+                }
+            }
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+            // A symlink already on disk could redirect the entry elsewhere, so
+            // check where the parent really is.
+            if !parent.canonicalize()?.starts_with(&self.root) {
+                return Err(anyhow!("Refusing to extract {}.", path.display()));
+            }
+        }
+        Ok(Some(relative))
+    }
+
+    // Resolves a hard link to its source under the destination. Unlike a
+    // symlink's target, the source is named from the archive root, so it is
+    // stripped like an entry path and must already exist within the
+    // destination.
+    fn link_source(&self, relative: &Path, link: &Path) -> Result<PathBuf> {
+        let refuse = || {
+            anyhow!(
+                "Refusing to extract {} linking to {}.",
+                relative.display(),
+                link.display()
+            )
+        };
+        let source = match self.relative(link) {
+            Ok(Some(source)) => self.destination.join(source),
+            _ => return Err(refuse()),
+        };
+        let resolved = source
+            .canonicalize()
+            .with_context(|| format!("Unable to extract {}.", relative.display()))?;
+        if !resolved.starts_with(&self.root) {
+            return Err(refuse());
+        }
+        Ok(source)
+    }
+
+    // Rejects symlinks whose target, relative to the link's own directory,
+    // would resolve outside the destination. The target is walked from the
+    // parent as it really is on disk, so links extracted earlier are followed
+    // rather than trusted to be as deep as their names suggest.
+    fn check_link(&self, relative: &Path, link: &Path) -> Result<()> {
+        let refuse = || {
+            anyhow!(
+                "Refusing to extract {} linking to {}.",
+                relative.display(),
+                link.display()
+            )
+        };
+        let mut resolved = match self.destination.join(relative).parent() {
+            Some(parent) => parent.canonicalize()?,
+            None => return Err(refuse()),
+        };
+        // Past a missing component a later entry could still become a link, so
+        // `..` can no longer be resolved.
+        let mut exists = true;
+        for component in link.components() {
+            match component {
+                Component::Normal(part) => {
+                    resolved.push(part);
+                    if exists {
+                        match resolved.canonicalize() {
+                            Ok(real) => resolved = real,
+                            Err(_) => exists = false,
+                        }
+                    }
+                }
+                Component::CurDir => {}
+                Component::ParentDir if exists => {
+                    resolved.pop();
+                }
+                _ => return Err(refuse()),
+            }
+            if !resolved.starts_with(&self.root) {
+                return Err(refuse());
+            }
+        }
+        Ok(())
+    }
+
+    // Maps an entry to a path under the destination, dropping the leading
+    // strip_components like `tar --strip-components`. Entries with nothing left
+    // are skipped.
+    fn relative(&self, path: &Path) -> Result<Option<PathBuf>> {
+        let mut parts = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::CurDir => {}
+                _ => return Err(anyhow!("Refusing to extract {}.", path.display())),
+            }
+        }
+        if parts.len() <= self.strip_components {
+            return Ok(None);
+        }
+        Ok(Some(parts[self.strip_components..].iter().collect()))
+    }
+}
+
 impl Decompress {
     // Input
     const PATH: &'static str = "path";
-    const DESTINATION: &'static str = "destination";
-    const PARAMS: [&'static str; 2] = [Decompress::PATH, Decompress::DESTINATION];
+    // Defaults to the directory containing the archive.
+    pub const DESTINATION: &'static str = "destination";
+    pub const STRIP_COMPONENTS: &'static str = "strip_components";
+    // `skip`, `overwrite` (default) or `error` when a file already exists.
+    pub const ON_CONFLICT: &'static str = "on_conflict";
+    // Forces `tar.gz`, `tar.bz2`, `tar.xz` or `zip` instead of detecting it.
+    pub const FORMAT: &'static str = "format";
+    const PARAMS: [&'static str; 5] = [
+        Decompress::PATH,
+        Decompress::DESTINATION,
+        Decompress::STRIP_COMPONENTS,
+        Decompress::ON_CONFLICT,
+        Decompress::FORMAT,
+    ];
+    const REQUIRED: [&'static str; 1] = [Decompress::PATH];
 
-    const OUTPUT: [&'static str; 0] = [];
-}
+    // Output
+    // The distinct first components of the extracted entries, one per line.
+    pub const TOP_LEVEL_PATHS: &'static str = "top_level_paths";
+    // Every file written, relative to the destination, one per line.
+    pub const EXTRACTED_FILES: &'static str = "extracted_files";
+    const OUTPUT: [&'static str; 2] = [Decompress::TOP_LEVEL_PATHS, Decompress::EXTRACTED_FILES];
 
-impl Workflow for Decompress {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
-        let path = input.parameter(Decompress::PATH);
-        let destination = input.parameter(Decompress::DESTINATION);
+    // Each extractor returns the paths it wrote, relative to the destination.
+    fn tar<R: Read>(
+        path: &str,
+        decoder: R,
+        compression: &str,
+        extraction: &Extraction,
+    ) -> Result<Vec<PathBuf>> {
+        let mut archive = Archive::new(decoder);
+        let corrupt = || format!("{} is not a valid {} tarball.", path, compression);
+        let mut extracted = Vec::new();
+        for entry in archive.entries().with_context(corrupt)? {
+            let mut entry = entry.with_context(corrupt)?;
+            let entry_path = entry.path().with_context(corrupt)?.into_owned();
+            let relative = match extraction.prepare(&entry_path)? {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let entry_type = entry.header().entry_type();
+            let target = extraction.destination.join(&relative);
+            let unable = || format!("Unable to extract {}.", entry_path.display());
+            if entry_type.is_symlink() || entry_type.is_hard_link() {
+                let link = entry
+                    .link_name()
+                    .with_context(corrupt)?
+                    .ok_or_else(|| anyhow!("{} is not a valid {} tarball.", path, compression))?;
+                if entry_type.is_hard_link() {
+                    // Entry::unpack would look for the source in the working
+                    // directory, and unpack_in cannot strip components.
+                    let source = extraction.link_source(&relative, &link)?;
+                    fs::hard_link(&source, &target).with_context(unable)?;
+                    extracted.push(relative);
+                    continue;
+                }
+                extraction.check_link(&relative, &link)?;
+            }
+            entry.unpack(&target).with_context(unable)?;
+            extracted.push(relative);
+        }
+        Ok(extracted)
+    }
+
+    fn zip(path: &str, file: File, extraction: &Extraction) -> Result<Vec<PathBuf>> {
+        let corrupt = || format!("{} is not a valid zip archive.", path);
+        let mut archive = ZipArchive::new(file).with_context(corrupt)?;
+        let mut extracted = Vec::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).with_context(corrupt)?;
+            Decompress::zip_entry(path, &mut entry, extraction, &mut extracted)?;
+        }
+        Ok(extracted)
+    }
 
-        let tarball = File::open(path)?;
-        let tar = GzDecoder::new(tarball);
-        let mut archive = Archive::new(tar);
-        archive.unpack(destination)?;
+    // Reads the entries in order from their local headers, so the archive
+    // never has to be seekable.
+    fn zip_stream<R: Read>(
+        path: &str,
+        mut reader: R,
+        extraction: &Extraction,
+    ) -> Result<Vec<PathBuf>> {
+        let corrupt = || format!("{} is not a valid zip archive.", path);
+        let mut extracted = Vec::new();
+        while let Some(mut entry) = read_zipfile_from_stream(&mut reader).with_context(corrupt)? {
+            Decompress::zip_entry(path, &mut entry, extraction, &mut extracted)?;
+        }
+        Ok(extracted)
+    }
 
-        if let Some(next) = context.next() {
-            next.execute(context, HashMap::new())?;
+    fn zip_entry(
+        path: &str,
+        entry: &mut ZipFile,
+        extraction: &Extraction,
+        extracted: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        // enclosed_name splits on `/` on every platform and rejects absolute
+        // paths and `..`.
+        let entry_path = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow!("Refusing to extract {}.", entry.name()))?;
+        let relative = match extraction.prepare(&entry_path)? {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        let target = extraction.destination.join(&relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            let mut file = File::create(&target)
+                .with_context(|| format!("Unable to create {}.", target.display()))?;
+            io::copy(entry, &mut file)
+                .with_context(|| format!("{} is not a valid zip archive.", path))?;
         }
+        extracted.push(relative);
         Ok(())
     }
 
+    // Extracts an archive as it is read, detecting the format from its first
+    // bytes or else from name. Used to unpack downloads without saving them.
+    pub fn extract<R: Read>(
+        name: &str,
+        mut reader: R,
+        destination: &Path,
+        input: &Inputs,
+    ) -> Result<Outputs> {
+        let mut header = Vec::new();
+        (&mut reader).take(6).read_to_end(&mut header)?;
+        let format = match input.parse(Decompress::FORMAT)? {
+            Some(format) => format,
+            None => Format::sniff(&header, name)?,
+        };
+        let reader = io::Cursor::new(header).chain(reader);
+        let extraction = Extraction::new(destination, input)?;
+        let extracted = match format {
+            Format::TarGz => Decompress::tar(name, GzDecoder::new(reader), "gzip", &extraction)?,
+            Format::TarBz2 => Decompress::tar(name, BzDecoder::new(reader), "bzip2", &extraction)?,
+            Format::TarXz => Decompress::tar(name, XzDecoder::new(reader), "xz", &extraction)?,
+            Format::Zip => Decompress::zip_stream(name, reader, &extraction)?,
+        };
+        Ok(extraction.outputs(&extracted))
+    }
+}
+
+impl Workflow for Decompress {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let path = input.parameter(Decompress::PATH);
+        let destination = match input.parameter(Decompress::DESTINATION) {
+            "" => Path::new(path)
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(".")),
+            destination => Path::new(destination),
+        };
+        let extraction = Extraction::new(destination, &input)?;
+        let format = match input.parse(Decompress::FORMAT)? {
+            Some(format) => format,
+            None => Format::detect(path)?,
+        };
+        let open = || File::open(path).with_context(|| format!("Unable to open {}.", path));
+        let extracted = match format {
+            Format::TarGz => Decompress::tar(path, GzDecoder::new(open()?), "gzip", &extraction)?,
+            Format::TarBz2 => Decompress::tar(path, BzDecoder::new(open()?), "bzip2", &extraction)?,
+            Format::TarXz => Decompress::tar(path, XzDecoder::new(open()?), "xz", &extraction)?,
+            Format::Zip => Decompress::zip(path, open()?, &extraction)?,
+        };
+        Ok(vec![extraction.outputs(&extracted)])
+    }
+
     fn parameters(&self) -> &'static [&'static str] {
-        return &Decompress::PARAMS;
+        &Decompress::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Decompress::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Decompress::OUTPUT;
+        &Decompress::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+    use bzip2::write::BzEncoder;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use xz2::write::XzEncoder;
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn tarball(path: &PathBuf, entries: &[(&str, &str)]) {
+        let encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        tar(encoder, entries).finish().unwrap();
+    }
+
+    fn tar<W: Write>(writer: W, entries: &[(&str, &str)]) -> W {
+        let mut builder = tar::Builder::new(writer);
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(path: &PathBuf, entries: &[(&str, &str)]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_tar_gz() {
+        let dir = scratch("tar-gz");
+        let archive = dir.join("archive.tar.gz");
+        tarball(&archive, &[("./app/bin/run", "run"), ("README", "readme")]);
+        let destination = dir.join("out");
+        run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::DESTINATION, destination.to_str().unwrap()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("app/bin/run")).unwrap(),
+            "run"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("README")).unwrap(),
+            "readme"
+        );
+
+        let corrupt = dir.join("corrupt.tar.gz");
+        fs::write(&corrupt, "not a tarball").unwrap();
+        let error = run(
+            Decompress {},
+            &[
+                (Decompress::PATH, corrupt.to_str().unwrap()),
+                (Decompress::DESTINATION, destination.to_str().unwrap()),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.to_string().ends_with("is not a valid gzip tarball."));
+    }
+
+    #[test]
+    fn test_zip() {
+        let dir = scratch("zip");
+        let archive = dir.join("archive.zip");
+        zip(&archive, &[("app/bin/run", "run"), ("README", "readme")]);
+        run(
+            Decompress {},
+            &[(Decompress::PATH, archive.to_str().unwrap())],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("app/bin/run")).unwrap(), "run");
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "readme");
+
+        let absolute = dir.join("absolute.zip");
+        zip(&absolute, &[("/etc/evil", "evil")]);
+        let error = run(
+            Decompress {},
+            &[(Decompress::PATH, absolute.to_str().unwrap())],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to extract /etc/evil.");
+    }
+
+    #[test]
+    fn test_destination() {
+        let dir = scratch("destination");
+        let archive = dir.join("archive.tar.gz");
+        tarball(&archive, &[("README", "readme")]);
+        let nested = dir.join("a/b/c");
+        run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::DESTINATION, nested.to_str().unwrap()),
+            ],
+        )
+        .unwrap();
+        assert!(nested.join("README").is_file());
+
+        let error = run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::DESTINATION, archive.to_str().unwrap()),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.to_string().ends_with("is a file, not a directory."));
+    }
+
+    #[test]
+    fn test_strip_components() {
+        let dir = scratch("strip-components");
+        let archive = dir.join("archive.tar.gz");
+        tarball(
+            &archive,
+            &[
+                ("project-1.2.3/bin/run", "run"),
+                ("project-1.2.3", ""),
+                ("top", ""),
+            ],
+        );
+        run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::STRIP_COMPONENTS, "1"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("bin/run")).unwrap(), "run");
+        assert!(!dir.join("project-1.2.3").exists());
+        assert!(!dir.join("top").exists());
+    }
+
+    #[test]
+    fn test_on_conflict() {
+        let dir = scratch("on-conflict");
+        let archive = dir.join("archive.zip");
+        zip(&archive, &[("README", "new"), ("LICENSE", "license")]);
+        let extract = |on_conflict: &str| {
+            fs::write(dir.join("README"), "old").unwrap();
+            let _ = fs::remove_file(dir.join("LICENSE"));
+            run(
+                Decompress {},
+                &[
+                    (Decompress::PATH, archive.to_str().unwrap()),
+                    (Decompress::ON_CONFLICT, on_conflict),
+                ],
+            )
+        };
+
+        extract("skip").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "old");
+        assert!(dir.join("LICENSE").is_file());
+
+        extract("overwrite").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "new");
+
+        let error = extract("error").unwrap_err();
+        assert!(error.to_string().ends_with("README already exists."));
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "old");
+
+        extract("").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "new");
+
+        assert!(extract("clobber").is_err());
+    }
+
+    #[test]
+    fn test_bzip2_and_xz() {
+        let dir = scratch("bzip2-xz");
+        let entries = [("README", "readme")];
+        let bz2 = dir.join("archive.tar.bz2");
+        let encoder = BzEncoder::new(File::create(&bz2).unwrap(), bzip2::Compression::best());
+        tar(encoder, &entries).finish().unwrap();
+        let xz = dir.join("archive.txz");
+        tar(XzEncoder::new(File::create(&xz).unwrap(), 6), &entries)
+            .finish()
+            .unwrap();
+
+        for (archive, out) in &[(bz2, "bz2"), (xz, "xz")] {
+            let destination = dir.join(out);
+            run(
+                Decompress {},
+                &[
+                    (Decompress::PATH, archive.to_str().unwrap()),
+                    (Decompress::DESTINATION, destination.to_str().unwrap()),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                fs::read_to_string(destination.join("README")).unwrap(),
+                "readme"
+            );
+        }
+
+        let unknown = dir.join("archive.rar");
+        fs::write(&unknown, "rar").unwrap();
+        let error = run(
+            Decompress {},
+            &[(Decompress::PATH, unknown.to_str().unwrap())],
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Unsupported archive format"));
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let dir = scratch("detect-format");
+        let archive = dir.join("download");
+        zip(&archive, &[("README", "readme")]);
+        let destination = dir.join("out");
+        run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::DESTINATION, destination.to_str().unwrap()),
+            ],
+        )
+        .unwrap();
+        assert!(destination.join("README").is_file());
+
+        let error = run(
+            Decompress {},
+            &[
+                (Decompress::PATH, archive.to_str().unwrap()),
+                (Decompress::DESTINATION, destination.to_str().unwrap()),
+                (Decompress::FORMAT, "tar.xz"),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.to_string().ends_with("is not a valid xz tarball."));
+    }
+
+    #[test]
+    fn test_path_traversal() {
+        let dir = scratch("path-traversal");
+        let destination = dir.join("out");
+        let extract = |entries: &[(&str, Option<&str>)]| {
+            let archive = dir.join("archive.tar.gz");
+            let encoder = GzEncoder::new(File::create(&archive).unwrap(), Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            for (name, link) in entries {
+                let mut header = tar::Header::new_gnu();
+                // Written by hand because set_path refuses `..`.
+                header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+                header.set_mode(0o644);
+                header.set_size(0);
+                if let Some(link) = link {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_link_name(link).unwrap();
+                }
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap();
+            run(
+                Decompress {},
+                &[
+                    (Decompress::PATH, archive.to_str().unwrap()),
+                    (Decompress::DESTINATION, destination.to_str().unwrap()),
+                ],
+            )
+        };
+
+        let error = extract(&[("../../evil", None)]).unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to extract ../../evil.");
+        assert!(!dir.join("evil").exists());
+
+        let error = extract(&[("link", Some("/etc"))]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to extract link linking to /etc."
+        );
+        let error = extract(&[("app/link", Some("../../.."))]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to extract app/link linking to ../../...",
+        );
+        extract(&[("app/link", Some("../README"))]).unwrap();
+
+        // `x/y` is only one level deep by name, but `x` is the destination
+        // itself by the time it is extracted.
+        let error = extract(&[("x", Some(".")), ("x/y", Some(".."))]).unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to extract x/y linking to ...",);
+        assert!(fs::symlink_metadata(destination.join("y")).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, destination.join("escape")).unwrap();
+            let error = extract(&[("escape/evil", None)]).unwrap_err();
+            assert_eq!(error.to_string(), "Refusing to extract escape/evil.");
+            assert!(!dir.join("evil").exists());
+        }
+    }
+
+    #[test]
+    fn test_hard_link() {
+        let dir = scratch("hard-link");
+        let destination = dir.join("out");
+        let extract = |entries: &[(&str, Option<&str>)], strip_components: &str| {
+            let archive = dir.join("archive.tar.gz");
+            let encoder = GzEncoder::new(File::create(&archive).unwrap(), Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            for (name, link) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_path(name).unwrap();
+                header.set_mode(0o644);
+                match link {
+                    Some(link) => {
+                        header.set_entry_type(tar::EntryType::Link);
+                        header.set_link_name(link).unwrap();
+                        header.set_size(0);
+                        header.set_cksum();
+                        builder.append(&header, io::empty()).unwrap();
+                    }
+                    None => {
+                        header.set_size(name.len() as u64);
+                        header.set_cksum();
+                        builder.append(&header, name.as_bytes()).unwrap();
+                    }
+                }
+            }
+            builder.into_inner().unwrap().finish().unwrap();
+            run(
+                Decompress {},
+                &[
+                    (Decompress::PATH, archive.to_str().unwrap()),
+                    (Decompress::DESTINATION, destination.to_str().unwrap()),
+                    (Decompress::STRIP_COMPONENTS, strip_components),
+                ],
+            )
+        };
+
+        extract(&[("a/c", None), ("a/b", Some("a/c"))], "0").unwrap();
+        assert_eq!(fs::read_to_string(destination.join("a/b")).unwrap(), "a/c");
+
+        extract(&[("top/a/c", None), ("top/a/b", Some("top/a/c"))], "1").unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("a/b")).unwrap(),
+            "top/a/c"
+        );
+
+        // Tests run in the crate root, which has a Cargo.toml but the
+        // destination does not.
+        let error = extract(&[("a/d", Some("Cargo.toml"))], "0").unwrap_err();
+        assert_eq!(error.to_string(), "Unable to extract a/d.");
+        assert!(!destination.join("a/d").exists());
+
+        fs::write(dir.join("secret"), "secret").unwrap();
+        let error = extract(&[("a/b", Some("../../secret"))], "0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to extract a/b linking to ../../secret."
+        );
+        let error = extract(&[("a/b", Some("../../etc/passwd"))], "0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to extract a/b linking to ../../etc/passwd."
+        );
+        let error = extract(&[("a/b", Some("/etc/passwd"))], "0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to extract a/b linking to /etc/passwd."
+        );
     }
 }
@@ -0,0 +1,96 @@
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use std::{collections::HashMap, fs, io::ErrorKind};
+
+pub struct Delete {}
+
+impl Delete {
+    // Input
+    const PATH: &'static str = "path";
+    // Directories are only removed, with everything in them, when this is true.
+    const RECURSIVE: &'static str = "recursive";
+    // Treats a path that is already gone as deleted.
+    const MISSING_OK: &'static str = "missing_ok";
+    const PARAMS: [&'static str; 3] = [Delete::PATH, Delete::RECURSIVE, Delete::MISSING_OK];
+    const REQUIRED: [&'static str; 1] = [Delete::PATH];
+
+    // Output
+    const OUTPUT: [&'static str; 1] = [Delete::PATH];
+}
+
+impl Workflow for Delete {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let path = input.parameter(Delete::PATH);
+        let recursive = input.flag(Delete::RECURSIVE, false)?;
+        let missing_ok = input.flag(Delete::MISSING_OK, false)?;
+
+        let removed = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                if !recursive {
+                    return Err(anyhow!(
+                        "{} is a directory, set recursive to delete it.",
+                        path
+                    ));
+                }
+                fs::remove_dir_all(path)
+            }
+            Ok(_) => fs::remove_file(path),
+            Err(e) if e.kind() == ErrorKind::NotFound && missing_ok => Ok(()),
+            Err(e) => Err(e),
+        };
+        removed.with_context(|| format!("Unable to delete {}.", path))?;
+
+        let mut result = HashMap::new();
+        result.insert(Delete::PATH.to_string(), path.to_string());
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Delete::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Delete::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Delete::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+
+    #[test]
+    fn test_delete() {
+        let dir = scratch("delete");
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin/tool"), "").unwrap();
+        let path = dir.to_str().unwrap();
+
+        assert_eq!(
+            run(Delete {}, &[(Delete::PATH, path)])
+                .unwrap_err()
+                .to_string(),
+            format!("{} is a directory, set recursive to delete it.", path)
+        );
+        run(
+            Delete {},
+            &[(Delete::PATH, path), (Delete::RECURSIVE, "true")],
+        )
+        .unwrap();
+        assert!(!dir.exists());
+
+        assert_eq!(
+            run(Delete {}, &[(Delete::PATH, path)])
+                .unwrap_err()
+                .to_string(),
+            format!("Unable to delete {}.", path)
+        );
+        run(
+            Delete {},
+            &[(Delete::PATH, path), (Delete::MISSING_OK, "true")],
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,1040 @@
+use crate::decompress::Decompress;
+use crate::hash::{hex, Algorithm};
+use crate::http::{ClientConfig, Http};
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use log::{info, warn};
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    blocking::{Client, Response},
+    header, StatusCode,
+};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    thread,
+};
+
+pub struct Download {}
+
+// What the first response holds.
+enum Fetched {
+    Whole,
+    // The rest of the partial file.
+    Rest,
+    // Only the first byte of a file of this size, which the server can send
+    // in ranges.
+    Ranges(u64),
+}
+
+// Hashes and counts what passes through, logging progress on the way.
+struct Tracked<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+    progress: Progress,
+}
+
+impl<R: Read> Tracked<R> {
+    // Starts from a hasher that has already seen `size` bytes.
+    fn new(inner: R, name: &Path, hasher: Sha256, size: u64, remaining: Option<u64>) -> Tracked<R> {
+        Tracked {
+            inner,
+            hasher,
+            size,
+            progress: Progress::new(name, size, remaining),
+        }
+    }
+
+    // Returns the size and sha256 of everything read.
+    fn finish(self) -> (u64, String) {
+        (self.size, hex(&self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..read]);
+        self.size += read as u64;
+        if let Some(message) = self.progress.advance(read as u64) {
+            info!("{}", message);
+        }
+        Ok(read)
+    }
+}
+
+// Reports each tenth of a download whose length is known, otherwise each
+// mebibyte.
+struct Progress {
+    name: String,
+    total: Option<u64>,
+    done: u64,
+    reported: u64,
+}
+
+impl Progress {
+    const UNKNOWN_STEP_BYTES: u64 = 1024 * 1024;
+
+    fn new(path: &Path, done: u64, remaining: Option<u64>) -> Progress {
+        let mut progress = Progress {
+            name: path.display().to_string(),
+            total: remaining.map(|remaining| done + remaining),
+            done,
+            reported: 0,
+        };
+        progress.reported = progress.step();
+        progress
+    }
+
+    fn step(&self) -> u64 {
+        match self.total {
+            Some(0) => 10,
+            Some(total) => self.done * 10 / total,
+            None => self.done / Progress::UNKNOWN_STEP_BYTES,
+        }
+    }
+
+    // Returns a message when the download has passed another step.
+    fn advance(&mut self, bytes: u64) -> Option<String> {
+        self.done += bytes;
+        let step = self.step();
+        if step <= self.reported {
+            return None;
+        }
+        self.reported = step;
+        Some(match self.total {
+            Some(total) => format!(
+                "Downloaded {}% of {} ({} of {} bytes).",
+                self.done * 100 / total,
+                self.name,
+                self.done,
+                total
+            ),
+            None => format!("Downloaded {} bytes of {}.", self.done, self.name),
+        })
+    }
+}
+
+impl Download {
+    // Input
+    // Either may list several mirrors, one per line, tried in order until
+    // one succeeds, including its checksum.
+    const URL: &'static str = "url";
+    const URLS: &'static str = "urls";
+    // Where the file is saved, under the name the server suggests in
+    // Content-Disposition, or else the last segment of the url. Defaults to
+    // the working directory.
+    const DIRECTORY: &'static str = "directory";
+    // Continues a partial file with a Range request instead of starting over.
+    // The partial file is looked up by the name taken from the url, and
+    // downloaded again when the server answers with some other range.
+    const RESUME: &'static str = "resume";
+    // Checked once the download completes. A file that fails either check
+    // is deleted rather than left for the next step.
+    const EXPECTED_SHA256: &'static str = "expected_sha256";
+    const EXPECTED_SIZE: &'static str = "expected_size";
+    // Fetches the file in this many ranges at once, or in one stream when
+    // the server does not support ranges.
+    const CONNECTIONS: &'static str = "connections";
+    // The exact path to save to, in place of directory and the derived name.
+    const OUTPUT_PATH: &'static str = "output";
+    // Replaces an existing file at output, which otherwise fails the step
+    // unless the download resumes it.
+    const OVERWRITE: &'static str = "overwrite";
+    // Extracts the archive into destination as it arrives instead of saving
+    // it, taking the other parameters of Decompress. The download is never
+    // resumed or split, and files extracted before a failed checksum are left
+    // in place.
+    const DECOMPRESS: &'static str = "decompress";
+    // As with Http, HTTP(S)_PROXY apply without a proxy.
+    const PROXY: &'static str = "proxy";
+    // Bounds connecting and each read rather than the whole download, 30
+    // seconds unless set.
+    const TIMEOUT_SECS: &'static str = "timeout_secs";
+    const PARAMS: [&'static str; 16] = [
+        Download::URL,
+        Download::URLS,
+        Download::DIRECTORY,
+        Download::OUTPUT_PATH,
+        Download::OVERWRITE,
+        Download::RESUME,
+        Download::EXPECTED_SHA256,
+        Download::EXPECTED_SIZE,
+        Download::CONNECTIONS,
+        Download::DECOMPRESS,
+        Download::PROXY,
+        Download::TIMEOUT_SECS,
+        Decompress::DESTINATION,
+        Decompress::STRIP_COMPONENTS,
+        Decompress::ON_CONFLICT,
+        Decompress::FORMAT,
+    ];
+
+    // Output
+    // The saved file, or the destination when decompressing.
+    const PATH: &'static str = "path";
+    // The url that was downloaded.
+    const MIRROR: &'static str = "mirror";
+    const OUTPUT: [&'static str; 4] = [
+        Download::PATH,
+        Download::MIRROR,
+        Decompress::TOP_LEVEL_PATHS,
+        Decompress::EXTRACTED_FILES,
+    ];
+
+    const DEFAULT_FILE_NAME: &'static str = "download";
+
+    fn file_name(url: &str) -> Result<String> {
+        let url = reqwest::Url::parse(url).with_context(|| format!("Invalid url {}.", url))?;
+        let name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default();
+        Ok(match name {
+            "" => Download::DEFAULT_FILE_NAME.to_string(),
+            name => name.to_string(),
+        })
+    }
+
+    fn get(client: &Client, url: &str, range: Option<String>) -> Result<Response> {
+        let mut request = client.get(url);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        let response = request.send()?;
+        let status = response.status();
+        if !status.is_success() && status != StatusCode::RANGE_NOT_SATISFIABLE {
+            return Err(anyhow!("Download of {} failed with {}.", url, status));
+        }
+        Ok(response)
+    }
+
+    // The `filename*` or `filename` of a Content-Disposition header, quoted
+    // or not. Only the final path component is kept, so a server cannot
+    // write outside the directory.
+    fn suggested_name(response: &Response) -> Option<String> {
+        let disposition = response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)?
+            .to_str()
+            .ok()?;
+        let mut names = HashMap::new();
+        let mut rest = disposition;
+        while let Some((_, parameter)) = rest.split_once(';') {
+            let (key, value) = parameter.split_once('=')?;
+            let value = value.trim_start();
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(quoted) => {
+                    let mut unquoted = String::new();
+                    let mut chars = quoted.char_indices();
+                    let end = loop {
+                        match chars.next()? {
+                            (i, '"') => break i + 1,
+                            (_, '\\') => unquoted.push(chars.next()?.1),
+                            (_, c) => unquoted.push(c),
+                        }
+                    };
+                    (unquoted, &quoted[end..])
+                }
+                None => {
+                    let end = value.find(';').unwrap_or(value.len());
+                    (value[..end].trim().to_string(), &value[end..])
+                }
+            };
+            names.insert(key.trim().to_lowercase(), value);
+            rest = remaining;
+        }
+        // RFC 5987: `charset'language'percent-encoded`.
+        let extended = names.get("filename*").and_then(|value| {
+            let (_, encoded) = value.rsplit_once('\'')?;
+            let decoded = percent_decode_str(encoded).decode_utf8().ok()?;
+            Some(decoded.into_owned())
+        });
+        let name = extended.or_else(|| names.remove("filename"))?;
+        let name = Path::new(&name).file_name()?.to_str()?.to_string();
+        Some(name)
+    }
+
+    // The start and total size of `bytes 6-10/11`, where the size may be `*`
+    // for unknown.
+    fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+        let range = response
+            .headers()
+            .get(header::CONTENT_RANGE)?
+            .to_str()
+            .ok()?
+            .strip_prefix("bytes ")?;
+        let (span, total) = range.split_once('/')?;
+        let (start, _) = span.split_once('-')?;
+        Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+    }
+
+    // Requests the rest of the file at partial when resuming, or only its
+    // first byte to learn whether it can be fetched in chunks. Servers without
+    // range support send the whole file instead.
+    fn fetch(
+        client: &Client,
+        url: &str,
+        partial: &Path,
+        resume: bool,
+        chunked: bool,
+    ) -> Result<(Response, Fetched)> {
+        let offset = match fs::metadata(partial) {
+            Ok(metadata) if resume => metadata.len(),
+            _ => 0,
+        };
+        let range = match offset {
+            0 if chunked => Some("bytes=0-0".to_string()),
+            0 => None,
+            offset => Some(format!("bytes={}-", offset)),
+        };
+        let response = Download::get(client, url, range)?;
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            match (offset, Download::content_range(&response)) {
+                (0, Some((_, Some(total)))) => return Ok((response, Fetched::Ranges(total))),
+                (0, _) => {}
+                // Appending any other range would corrupt the partial file.
+                (offset, Some((start, _))) if start == offset => {
+                    return Ok((response, Fetched::Rest))
+                }
+                (offset, _) => warn!(
+                    "{} did not continue {} from byte {}, downloading it again.",
+                    url,
+                    partial.display(),
+                    offset
+                ),
+            }
+        } else if response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok((response, Fetched::Whole));
+        }
+        Ok((Download::get(client, url, None)?, Fetched::Whole))
+    }
+
+    // Splits the file into one range per connection and writes each into
+    // place as it arrives. Returns the size and sha256 of the file.
+    fn save_chunks(
+        client: &Client,
+        url: &str,
+        path: &Path,
+        total: u64,
+        connections: u64,
+    ) -> Result<(u64, String)> {
+        File::create(path)
+            .and_then(|file| file.set_len(total))
+            .with_context(|| format!("Unable to create {}.", path.display()))?;
+        let chunk = total.div_ceil(connections);
+        let results: Vec<Result<()>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..connections)
+                .map(|i| i * chunk)
+                .filter(|&start| start < total)
+                .map(|start| {
+                    let end = total.min(start + chunk) - 1;
+                    scope.spawn(move || -> Result<()> {
+                        let range = format!("bytes={}-{}", start, end);
+                        let response = Download::get(client, url, Some(range))?;
+                        if response.status() != StatusCode::PARTIAL_CONTENT {
+                            return Err(anyhow!("{} ignored the range {}-{}.", url, start, end));
+                        }
+                        let mut file = OpenOptions::new().write(true).open(path)?;
+                        file.seek(SeekFrom::Start(start))?;
+                        let length = end - start + 1;
+                        if io::copy(&mut response.take(length), &mut file)? < length {
+                            return Err(anyhow!(
+                                "The range {}-{} of {} ended early.",
+                                start,
+                                end,
+                                url
+                            ));
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("A download connection panicked.")))
+                })
+                .collect()
+        });
+        results.into_iter().collect::<Result<()>>()?;
+        info!(
+            "Downloaded {} bytes of {} in chunks.",
+            total,
+            path.display()
+        );
+
+        let sha256 = Algorithm::Sha256.digest(&mut File::open(path)?)?;
+        Ok((total, sha256))
+    }
+
+    // Saves the body to path, appending when it continues a partial file, and
+    // logs progress with -v. Returns the size and sha256 of the whole file,
+    // hashed as it is written.
+    fn save(response: Response, path: &Path, append: bool) -> Result<(u64, String)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| format!("Unable to create {}.", path.display()))?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        if append {
+            size = io::copy(&mut File::open(path)?, &mut hasher)?;
+        }
+        let remaining = response.content_length();
+        let mut tracked = Tracked::new(response, path, hasher, size, remaining);
+        io::copy(&mut tracked, &mut file)?;
+        Ok(tracked.finish())
+    }
+
+    fn extract(client: &Client, url: &str, directory: &str, input: &Inputs) -> Result<Outputs> {
+        let destination = match (input.parameter(Decompress::DESTINATION), directory) {
+            ("", "") => Path::new("."),
+            ("", directory) => Path::new(directory),
+            (destination, _) => Path::new(destination),
+        };
+        let response = Download::get(client, url, None)?;
+        let name = match Download::suggested_name(&response) {
+            Some(name) => name,
+            None => Download::file_name(url)?,
+        };
+        let remaining = response.content_length();
+        let mut tracked = Tracked::new(response, Path::new(&name), Sha256::new(), 0, remaining);
+        let mut result = Decompress::extract(&name, &mut tracked, destination, input)?;
+        // Archives can end before the body does, and the checksum covers it all.
+        io::copy(&mut tracked, &mut io::sink())?;
+        let (size, sha256) = tracked.finish();
+        Download::verify(url, size, &sha256, input)?;
+
+        result.insert(
+            Download::PATH.to_string(),
+            destination.display().to_string(),
+        );
+        Ok(result)
+    }
+
+    // Fetches url into place, or extracts it with decompress.
+    fn download(client: &Client, url: &str, input: &Inputs) -> Result<Outputs> {
+        let directory = input.parameter(Download::DIRECTORY);
+        let output = input.parameter(Download::OUTPUT_PATH);
+        let resume = input.flag(Download::RESUME, false)?;
+        let connections = input.parse(Download::CONNECTIONS)?.unwrap_or(1).max(1);
+
+        if input.flag(Download::DECOMPRESS, false)? {
+            return Download::extract(client, url, directory, input);
+        }
+        let partial = match output {
+            "" => PathBuf::from(directory).join(Download::file_name(url)?),
+            output => PathBuf::from(output),
+        };
+        if let Some(parent) = partial.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let (response, fetched) = Download::fetch(client, url, &partial, resume, connections > 1)?;
+        let path = match (Download::suggested_name(&response), &fetched) {
+            (Some(name), Fetched::Whole | Fetched::Ranges(_)) if output.is_empty() => {
+                PathBuf::from(directory).join(name)
+            }
+            _ => partial,
+        };
+        let (size, sha256) = match fetched {
+            Fetched::Whole => Download::save(response, &path, false)?,
+            Fetched::Rest => Download::save(response, &path, true)?,
+            Fetched::Ranges(total) => {
+                Download::save_chunks(client, url, &path, total, connections)?
+            }
+        };
+        let name = path.display().to_string();
+        if let Err(e) = Download::verify(&name, size, &sha256, input) {
+            fs::remove_file(&path)?;
+            return Err(e);
+        }
+
+        let mut result = HashMap::new();
+        result.insert(Download::PATH.to_string(), name);
+        Ok(result)
+    }
+
+    fn verify(name: &str, size: u64, sha256: &str, input: &Inputs) -> Result<()> {
+        let expected_sha256 = input.parameter(Download::EXPECTED_SHA256).trim();
+        let expected_size: Option<u64> = input.parse(Download::EXPECTED_SIZE)?;
+        let problem = if let Some(expected) = expected_size.filter(|&expected| expected != size) {
+            format!(
+                "{} has {} bytes, but {} are expected.",
+                name, size, expected
+            )
+        } else if !expected_sha256.is_empty() && !expected_sha256.eq_ignore_ascii_case(sha256) {
+            format!(
+                "The sha256 of {} is {}, but {} is expected.",
+                name, sha256, expected_sha256
+            )
+        } else {
+            return Ok(());
+        };
+        Err(anyhow!(problem))
+    }
+}
+
+impl Workflow for Download {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let mirrors: Vec<_> = input
+            .parameter(Download::URL)
+            .lines()
+            .chain(input.parameter(Download::URLS).lines())
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .collect();
+        let output = input.parameter(Download::OUTPUT_PATH);
+        let resume = input.flag(Download::RESUME, false)?;
+        let overwrite = input.flag(Download::OVERWRITE, false)?;
+
+        if mirrors.is_empty() {
+            return Err(anyhow!("Download needs a url."));
+        }
+        if !output.is_empty() && Path::new(output).exists() && !overwrite && !resume {
+            return Err(anyhow!(
+                "{} already exists, set overwrite to replace it.",
+                output
+            ));
+        }
+        let config = ClientConfig::new(
+            input.parameter(Download::PROXY),
+            input.parse(Download::TIMEOUT_SECS)?,
+        );
+        let client = Http::client(context, config)?;
+        let mut failures = Vec::new();
+        for mirror in &mirrors {
+            match Download::download(&client, mirror, &input) {
+                Ok(mut result) => {
+                    result.insert(Download::MIRROR.to_string(), mirror.to_string());
+                    return Ok(vec![result]);
+                }
+                Err(e) if mirrors.len() == 1 => return Err(e),
+                Err(e) => {
+                    warn!("Download from {} failed: {:#}", mirror, e);
+                    failures.push(format!("{}: {:#}", mirror, e));
+                }
+            }
+        }
+        Err(anyhow!("Every mirror failed.\n{}", failures.join("\n")))
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Download::PARAMS
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Download::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{read_request, response, run, scratch, serve};
+    use flate2::{write::GzEncoder, Compression};
+    use std::{
+        io::Write,
+        net::TcpListener,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use zip::{write::FileOptions, ZipWriter};
+
+    // Serves body with range support on concurrent connections, pausing
+    // after every 64 KiB sent to stand in for a slow link.
+    fn serve_ranges(body: Vec<u8>, pause: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let body = Arc::new(body);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let body = body.clone();
+                thread::spawn(move || {
+                    let request = match read_request(&mut stream) {
+                        Some(request) => request.to_lowercase(),
+                        None => return,
+                    };
+                    let range = request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .and_then(|range| range.split_once('-'));
+                    let (start, end) = range
+                        .map(|(start, end)| {
+                            (
+                                start.parse().unwrap(),
+                                end.parse().unwrap_or(body.len() - 1),
+                            )
+                        })
+                        .unwrap_or((0, body.len() - 1));
+                    let status = match range {
+                        Some(_) => format!(
+                            "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+                            start,
+                            end,
+                            body.len()
+                        ),
+                        None => "200 OK".to_string(),
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        end + 1 - start
+                    );
+                    let _ = stream.write_all(head.as_bytes());
+                    for part in body[start..=end].chunks(64 * 1024) {
+                        if stream.write_all(part).is_err() {
+                            return;
+                        }
+                        thread::sleep(pause);
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = scratch("download-resume");
+        let (ranged, _) = serve(|request| match request.contains("range: bytes=6-") {
+            true => response(
+                "206 Partial Content\r\nContent-Range: bytes 6-10/11",
+                "world",
+            ),
+            false => response("200 OK", "hello world"),
+        });
+        let url = format!("{}/releases/tool.tar.gz", ranged);
+        fs::write(dir.join("tool.tar.gz"), "hello ").unwrap();
+
+        let outputs = run(
+            Download {},
+            &[
+                (Download::URL, &url),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::RESUME, "true"),
+            ],
+        )
+        .unwrap();
+        let path = &outputs[0][Download::PATH];
+        assert_eq!(path, dir.join("tool.tar.gz").to_str().unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "hello world");
+
+        // A range other than the one asked for starts over rather than
+        // being appended.
+        let (misranged, requests) = serve(|request| match request.contains("range: bytes=6-") {
+            true => response(
+                "206 Partial Content\r\nContent-Range: bytes 0-10/11",
+                "hello world",
+            ),
+            false => response("200 OK", "hello world"),
+        });
+        fs::write(path, "hello ").unwrap();
+        run(
+            Download {},
+            &[
+                (Download::URL, &format!("{}/tool.tar.gz", misranged)),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::RESUME, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "hello world");
+        assert!(requests.recv().unwrap().contains("range: bytes=6-"));
+        assert!(!requests.recv().unwrap().contains("range:"));
+
+        // Without ranges the partial file is replaced.
+        let (unranged, _) = serve(|_| response("200 OK", "hello world"));
+        fs::write(path, "hello ").unwrap();
+        run(
+            Download {},
+            &[
+                (Download::URL, &format!("{}/tool.tar.gz", unranged)),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::RESUME, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_checksum() {
+        let dir = scratch("download-checksum");
+        let (address, _) = serve(|request| match request.contains("range: bytes=3-") {
+            true => response("206 Partial Content\r\nContent-Range: bytes 3-5/6", "def"),
+            false => response("200 OK", "abcdef"),
+        });
+        let url = format!("{}/tool.zip", address);
+        let sha256 = "bef57ec7f53a6d40beb640a780a639c83bc29ac8a9816f1fc6c5c6dcd93c4721";
+        let path = dir.join("tool.zip");
+        let directory = dir.to_str().unwrap();
+
+        // The hash covers the part that was already on disk.
+        fs::write(&path, "abc").unwrap();
+        run(
+            Download {},
+            &[
+                (Download::URL, &url),
+                (Download::DIRECTORY, directory),
+                (Download::RESUME, "true"),
+                (Download::EXPECTED_SHA256, sha256),
+                (Download::EXPECTED_SIZE, "6"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            run(
+                Download {},
+                &[
+                    (Download::URL, &url),
+                    (Download::DIRECTORY, directory),
+                    (Download::EXPECTED_SHA256, &sha256.replace('b', "c")),
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!(
+                "The sha256 of {} is {}, but {} is expected.",
+                path.display(),
+                sha256,
+                sha256.replace('b', "c")
+            )
+        );
+        assert!(!path.exists());
+        assert_eq!(
+            run(
+                Download {},
+                &[
+                    (Download::URL, &url),
+                    (Download::DIRECTORY, directory),
+                    (Download::EXPECTED_SIZE, "7"),
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!("{} has 6 bytes, but 7 are expected.", path.display())
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        let dir = scratch("download-disposition");
+        let (address, _) = serve(|request| {
+            let disposition = match request.split_once(' ').unwrap().1 {
+                path if path.starts_with("/quoted") => r#"attachment; filename="tool \"v2\".zip""#,
+                path if path.starts_with("/encoded") => {
+                    "attachment; filename=fallback.zip; filename*=UTF-8''t%C3%B6ol%20v2.zip"
+                }
+                path if path.starts_with("/traversal") => "attachment; filename=../../tool.zip",
+                _ => "inline",
+            };
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Disposition: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                disposition
+            )
+        });
+        let path = |url: &str| {
+            let outputs = run(
+                Download {},
+                &[
+                    (Download::URL, &format!("{}{}", address, url)),
+                    (Download::DIRECTORY, dir.to_str().unwrap()),
+                ],
+            )
+            .unwrap();
+            outputs[0][Download::PATH].clone()
+        };
+        let expected = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        assert_eq!(path("/quoted?id=1"), expected("tool \"v2\".zip"));
+        assert_eq!(path("/encoded"), expected("töol v2.zip"));
+        assert_eq!(path("/traversal"), expected("tool.zip"));
+        assert_eq!(path("/releases/latest.tgz"), expected("latest.tgz"));
+    }
+
+    #[test]
+    fn test_output() {
+        let dir = scratch("download-output");
+        let (address, _) = serve(|_| {
+            "HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=other.zip\r\n\
+             Content-Length: 2\r\nConnection: close\r\n\r\nv2"
+                .to_string()
+        });
+        let url = format!("{}/tool.zip", address);
+        let output = dir.join("bin/tool");
+        let output = output.to_str().unwrap();
+
+        let outputs = run(
+            Download {},
+            &[(Download::URL, &url), (Download::OUTPUT_PATH, output)],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Download::PATH], output);
+        assert_eq!(fs::read_to_string(output).unwrap(), "v2");
+
+        fs::write(output, "v1").unwrap();
+        assert_eq!(
+            run(
+                Download {},
+                &[(Download::URL, &url), (Download::OUTPUT_PATH, output)]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!("{} already exists, set overwrite to replace it.", output)
+        );
+        assert_eq!(fs::read_to_string(output).unwrap(), "v1");
+        run(
+            Download {},
+            &[
+                (Download::URL, &url),
+                (Download::OUTPUT_PATH, output),
+                (Download::OVERWRITE, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(output).unwrap(), "v2");
+    }
+
+    #[test]
+    fn test_decompress() {
+        let dir = scratch("download-decompress");
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o755);
+        builder
+            .append_data(&mut header, "tool-1.0/bin/tool", &b"run"[..])
+            .unwrap();
+        let tarball = builder.into_inner().unwrap().finish().unwrap();
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        zip.start_file("README", FileOptions::default()).unwrap();
+        zip.write_all(b"readme").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        let destination = dir.join("opt");
+        let outputs = run(
+            Download {},
+            &[
+                (
+                    Download::URL,
+                    &format!("{}/latest", serve_ranges(tarball, Duration::ZERO)),
+                ),
+                (Download::DECOMPRESS, "true"),
+                (Decompress::DESTINATION, destination.to_str().unwrap()),
+                (Decompress::STRIP_COMPONENTS, "1"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Download::PATH], destination.to_str().unwrap());
+        assert_eq!(outputs[0][Decompress::EXTRACTED_FILES], "bin/tool");
+        assert_eq!(
+            fs::read_to_string(destination.join("bin/tool")).unwrap(),
+            "run"
+        );
+
+        run(
+            Download {},
+            &[
+                (
+                    Download::URL,
+                    &format!("{}/readme", serve_ranges(zip, Duration::ZERO)),
+                ),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::DECOMPRESS, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("README")).unwrap(), "readme");
+        assert!(!dir.join("readme").exists());
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let dir = scratch("download-mirrors");
+        let missing = format!("{}/tool.zip", serve(|_| response("404 Not Found", "")).0);
+        let corrupt = format!("{}/tool.zip", serve(|_| response("200 OK", "corrupt")).0);
+        let good = format!("{}/tool.zip", serve(|_| response("200 OK", "abcdef")).0);
+        let sha256 = "bef57ec7f53a6d40beb640a780a639c83bc29ac8a9816f1fc6c5c6dcd93c4721";
+
+        let outputs = run(
+            Download {},
+            &[
+                (Download::URL, &format!("{}\n{}", missing, corrupt)),
+                (Download::URLS, &good),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::EXPECTED_SHA256, sha256),
+            ],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Download::MIRROR], good);
+        assert_eq!(
+            fs::read_to_string(&outputs[0][Download::PATH]).unwrap(),
+            "abcdef"
+        );
+
+        let error = run(
+            Download {},
+            &[
+                (Download::URLS, &format!("{}\n{}", missing, corrupt)),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::EXPECTED_SHA256, sha256),
+            ],
+        )
+        .unwrap_err();
+        let lines: Vec<_> = error.to_string().lines().map(str::to_string).collect();
+        assert_eq!(lines[0], "Every mirror failed.");
+        assert_eq!(
+            lines[1],
+            format!(
+                "{}: Download of {} failed with 404 Not Found.",
+                missing, missing
+            )
+        );
+        assert!(lines[2].starts_with(&format!("{}: The sha256 of", corrupt)));
+    }
+
+    #[test]
+    fn test_progress() {
+        let path = Path::new("tool.zip");
+        let mut progress = Progress::new(path, 0, Some(1000));
+        assert_eq!(progress.advance(50), None);
+        assert_eq!(
+            progress.advance(200).unwrap(),
+            "Downloaded 25% of tool.zip (250 of 1000 bytes)."
+        );
+        assert_eq!(progress.advance(40), None);
+        assert_eq!(
+            progress.advance(710).unwrap(),
+            "Downloaded 100% of tool.zip (1000 of 1000 bytes)."
+        );
+
+        // A resumed download counts what was already on disk.
+        let mut progress = Progress::new(path, 500, Some(500));
+        assert_eq!(progress.advance(50), None);
+        assert_eq!(
+            progress.advance(50).unwrap(),
+            "Downloaded 60% of tool.zip (600 of 1000 bytes)."
+        );
+
+        let mut progress = Progress::new(path, 0, None);
+        assert_eq!(progress.advance(1000), None);
+        assert_eq!(
+            progress.advance(Progress::UNKNOWN_STEP_BYTES).unwrap(),
+            "Downloaded 1049576 bytes of tool.zip."
+        );
+    }
+
+    #[test]
+    fn test_chunked() {
+        let dir = scratch("download-chunked");
+        let body: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let sha256 = Algorithm::Sha256.digest(&mut &body[..]).unwrap();
+        let ranged = serve_ranges(body.clone(), Duration::ZERO);
+        let outputs = run(
+            Download {},
+            &[
+                (Download::URL, &format!("{}/tool.bin", ranged)),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::CONNECTIONS, "3"),
+                (Download::EXPECTED_SHA256, &sha256),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read(&outputs[0][Download::PATH]).unwrap(), body);
+
+        // Servers without ranges send the whole file in one stream.
+        let (unranged, _) = serve(|_| response("200 OK", "hello world"));
+        let outputs = run(
+            Download {},
+            &[
+                (Download::URL, &format!("{}/hello.txt", unranged)),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::CONNECTIONS, "3"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&outputs[0][Download::PATH]).unwrap(),
+            "hello world"
+        );
+    }
+
+    // Run with `cargo test -- --ignored --nocapture bench_chunked`.
+    #[test]
+    #[ignore]
+    fn bench_chunked() {
+        let dir = scratch("download-bench");
+        let address = serve_ranges(vec![0; 16 * 1024 * 1024], Duration::from_millis(5));
+        let url = format!("{}/large.bin", address);
+        for connections in ["1", "4", "8"] {
+            let start = Instant::now();
+            run(
+                Download {},
+                &[
+                    (Download::URL, &url),
+                    (Download::DIRECTORY, dir.to_str().unwrap()),
+                    (Download::CONNECTIONS, connections),
+                ],
+            )
+            .unwrap();
+            println!(
+                "16 MiB over {} connections: {:?}",
+                connections,
+                start.elapsed()
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_download() {
+        let dir = scratch("download-failed");
+        let (address, _) = serve(|_| response("404 Not Found", ""));
+        let url = format!("{}/missing.zip", address);
+        assert_eq!(
+            run(
+                Download {},
+                &[
+                    (Download::URL, &url),
+                    (Download::DIRECTORY, dir.to_str().unwrap())
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!("Download of {} failed with 404 Not Found.", url)
+        );
+    }
+
+    #[test]
+    fn test_proxy() {
+        let dir = scratch("download-proxy");
+        let (proxy, server) = serve(|_| response("200 OK", "v1"));
+        let outputs = run(
+            Download {},
+            &[
+                (Download::URL, "http://example.invalid/tool.zip"),
+                (Download::DIRECTORY, dir.to_str().unwrap()),
+                (Download::PROXY, &proxy),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&outputs[0][Download::PATH]).unwrap(),
+            "v1"
+        );
+        assert!(server
+            .recv()
+            .unwrap()
+            .contains(" http://example.invalid/tool.zip HTTP/1.1"));
+    }
+}
@@ -1,33 +1,223 @@
-use crate::{Context, Input, Inputs, Workflow};
-use anyhow::Result;
-use std::collections::HashMap;
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{Context as _, Result};
+use serde_json::json;
+use std::io::Write;
+use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    fs::OpenOptions,
+};
+use strum::EnumString;
 
 pub struct Echo {}
 
+#[derive(Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum Format {
+    Text,
+    Json,
+}
+
 impl Echo {
     // Input
     const TEXT: &'static str = "text";
-    const PARAMS: [&'static str; 1] = [Echo::TEXT];
+    // Writes the text to this file instead of stdout.
+    const OUTPUT_FILE: &'static str = "output_file";
+    // Adds to the end of output_file rather than replacing it.
+    const APPEND: &'static str = "append";
+    // `text` (default) prints text, `json` prints the previous step's outputs
+    // as an object.
+    const FORMAT: &'static str = "format";
+    // Prints the previous step's outputs, the history of the steps before it
+    // and, with include_env, the environment, with credential-like values
+    // masked unless the config turns that off.
+    const DUMP_CONTEXT: &'static str = "dump_context";
+    const INCLUDE_ENV: &'static str = "include_env";
+    const PARAMS: [&'static str; 6] = [
+        Echo::TEXT,
+        Echo::OUTPUT_FILE,
+        Echo::APPEND,
+        Echo::FORMAT,
+        Echo::DUMP_CONTEXT,
+        Echo::INCLUDE_ENV,
+    ];
 
     const OUTPUT: [&'static str; 0] = [];
+
+    fn dump(context: &Context, include_env: bool) -> Result<String> {
+        let masked = |map: &HashMap<String, String>| -> BTreeMap<String, String> {
+            map.iter()
+                .map(|(key, value)| (key.clone(), context.mask(key, value).to_string()))
+                .collect()
+        };
+        let mut dump = json!({ "input": masked(&context.input) });
+        if !context.history.is_empty() {
+            let history: Vec<_> = context
+                .history
+                .iter()
+                .map(|(step, outputs)| json!({ "step": step, "outputs": masked(outputs) }))
+                .collect();
+            dump["history"] = json!(history);
+        }
+        if include_env {
+            dump["env"] = json!(masked(&context.env));
+        }
+        Ok(serde_json::to_string_pretty(&dump)?)
+    }
+
+    fn write(path: &str, text: &str, append: bool) -> Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| format!("Unable to open {}.", path))?;
+        writeln!(file, "{}", text)?;
+        Ok(())
+    }
 }
 
 impl Workflow for Echo {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
-        let text = input.parameter(Echo::TEXT);
-
-        println!("{}", text);
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let dump_context = input.flag(Echo::DUMP_CONTEXT, false)?;
+        let include_env = input.flag(Echo::INCLUDE_ENV, false)?;
+        let format = input.parse(Echo::FORMAT)?.unwrap_or(Format::Text);
+        let text = if dump_context {
+            Echo::dump(context, include_env)?
+        } else {
+            match format {
+                Format::Text => input.parameter(Echo::TEXT).to_string(),
+                Format::Json => {
+                    let outputs: BTreeMap<_, _> = context.input.iter().collect();
+                    serde_json::to_string_pretty(&outputs)?
+                }
+            }
+        };
 
-        if let Some(next) = context.next() {
-            next.execute(context, HashMap::new())?;
+        match input.parameter(Echo::OUTPUT_FILE) {
+            "" => println!("{}", text),
+            path => Echo::write(path, &text, input.flag(Echo::APPEND, false)?)?,
         }
-        Ok(())
+
+        Ok(vec![HashMap::new()])
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Echo::PARAMS;
+        &Echo::PARAMS
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Echo::OUTPUT;
+        &Echo::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{context, run, run_in, scratch};
+
+    #[test]
+    fn test_output_file() {
+        let path = scratch("echo-output-file").join("logs/echo.log");
+        let path = path.to_str().unwrap();
+
+        run(Echo {}, &[(Echo::TEXT, "first"), (Echo::OUTPUT_FILE, path)]).unwrap();
+        run(
+            Echo {},
+            &[
+                (Echo::TEXT, "second"),
+                (Echo::OUTPUT_FILE, path),
+                (Echo::APPEND, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "first\nsecond\n");
+
+        run(Echo {}, &[(Echo::TEXT, "third"), (Echo::OUTPUT_FILE, path)]).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "third\n");
+    }
+
+    #[test]
+    fn test_json_format() {
+        let path = scratch("echo-json").join("echo.json");
+        let path = path.to_str().unwrap();
+        let mut context = context();
+        context
+            .input
+            .insert("status_code".to_string(), "200".to_string());
+        context.input.insert("text".to_string(), "ok".to_string());
+
+        run_in(
+            &mut context,
+            Echo {},
+            &[(Echo::FORMAT, "json"), (Echo::OUTPUT_FILE, path)],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "{\n  \"status_code\": \"200\",\n  \"text\": \"ok\"\n}\n"
+        );
+        assert!(run(Echo {}, &[(Echo::FORMAT, "yaml")]).is_err());
+    }
+
+    #[test]
+    fn test_dump_context() {
+        let path = scratch("echo-dump").join("context.json");
+        let path = path.to_str().unwrap();
+        let mut context = context();
+        context.input.insert("text".to_string(), "ok".to_string());
+        context
+            .input
+            .insert("token".to_string(), "ghp_123".to_string());
+        context.env = vec![("HOME".to_string(), "/root".to_string())]
+            .into_iter()
+            .collect();
+
+        run_in(
+            &mut context,
+            Echo {},
+            &[(Echo::DUMP_CONTEXT, "true"), (Echo::OUTPUT_FILE, path)],
+        )
+        .unwrap();
+        let dump: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            dump,
+            serde_json::json!({"input": {"text": "ok", "token": "***"}})
+        );
+
+        run_in(
+            &mut context,
+            Echo {},
+            &[
+                (Echo::DUMP_CONTEXT, "true"),
+                (Echo::INCLUDE_ENV, "true"),
+                (Echo::OUTPUT_FILE, path),
+            ],
+        )
+        .unwrap();
+        let dump: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(dump["env"], serde_json::json!({"HOME": "/root"}));
+
+        context
+            .history
+            .push(("fetch".to_string(), context.input.clone()));
+        run_in(
+            &mut context,
+            Echo {},
+            &[(Echo::DUMP_CONTEXT, "true"), (Echo::OUTPUT_FILE, path)],
+        )
+        .unwrap();
+        let dump: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            dump["history"],
+            serde_json::json!([{"step": "fetch", "outputs": {"text": "ok", "token": "***"}}])
+        );
     }
 }
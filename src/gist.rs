@@ -1,28 +1,49 @@
-use crate::{Context, Input, Inputs, Workflow, USER_AGENT};
-use anyhow::Result;
+use crate::http::{ClientConfig, Http};
+use crate::util::pairs;
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use log::warn;
 use reqwest::blocking::Client;
-use reqwest::blocking::Response;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fs};
 use strum::EnumString;
 
 pub struct Gist {}
 
 #[derive(Debug, EnumString)]
+#[strum(serialize_all = "UPPERCASE")]
 enum GistAction {
-    GET,
-    UPDATE,
+    Get,
+    Create,
+    Update,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 struct GistPayload<'a> {
-    #[serde(borrow)]
-    files: HashMap<&'a str, GistFile<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public: Option<bool>,
+    files: HashMap<&'a str, GistFile>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GistFile<'a> {
-    content: &'a str,
+#[derive(Debug, Serialize)]
+struct GistFile {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GistResponse {
+    id: String,
+    html_url: String,
+    files: HashMap<String, GistResponseFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GistResponseFile {
+    content: String,
 }
 
 impl Gist {
@@ -31,81 +52,325 @@ impl Gist {
     // Input
     const ACTION: &'static str = "action";
     const GIST_ID: &'static str = "gist_id";
+    // Optional for reading public gists, passed as `Authorization: token ...`.
+    const TOKEN: &'static str = "token";
+    // The older name for token, still accepted.
     const ACCESS_TOKEN: &'static str = "access_token";
     const FILE_NAME: &'static str = "file_name";
-    const PARAMS: [&'static str; 5] = [
+    // One `name=content` or `name=@path` per line, uploaded alongside file_name.
+    const FILES: &'static str = "files";
+    // Only used when creating a gist.
+    const DESCRIPTION: &'static str = "description";
+    const PUBLIC: &'static str = "public";
+    // As with Http, HTTP(S)_PROXY apply without a proxy.
+    const PROXY: &'static str = "proxy";
+    // 30 seconds unless set, as with Http.
+    const TIMEOUT_SECS: &'static str = "timeout_secs";
+    const PARAMS: [&'static str; 11] = [
         Gist::ACTION,
         Gist::GIST_ID,
+        Gist::TOKEN,
         Gist::ACCESS_TOKEN,
         Gist::FILE_NAME,
+        Gist::FILES,
         Gist::TEXT,
+        Gist::DESCRIPTION,
+        Gist::PUBLIC,
+        Gist::PROXY,
+        Gist::TIMEOUT_SECS,
     ];
+    const REQUIRED: [&'static str; 1] = [Gist::ACTION];
 
     // Output
     const STATUS_CODE: &'static str = "status_code";
-    const OUTPUT: [&'static str; 2] = [Gist::STATUS_CODE, Gist::TEXT];
+    const ID: &'static str = "id";
+    const HTML_URL: &'static str = "html_url";
+    const OUTPUT: [&'static str; 4] = [Gist::STATUS_CODE, Gist::TEXT, Gist::ID, Gist::HTML_URL];
 
-    fn update(gist_id: &str, access_token: &str, file_name: &str, text: &str) -> Result<Response> {
-        let url = format!("https://api.github.com/gists/{}", gist_id);
+    const API: &'static str = "https://api.github.com";
 
+    // Adds the headers every GitHub API call needs. The token only ever goes
+    // into the Authorization header so it cannot leak into error messages.
+    fn send(request: RequestBuilder, token: &str) -> Result<Response> {
+        let request = request.header("Accept", "application/vnd.github.v3+json");
+        let request = match token {
+            "" => {
+                warn!("Gist requests without a token are limited to 60 per hour.");
+                request
+            }
+            token => request.header("Authorization", format!("token {}", token)),
+        };
+        request
+            .send()
+            .map_err(|e| anyhow!("Gist request failed: {}.", e))
+    }
+
+    fn files<'a>(
+        file_name: &'a str,
+        text: &str,
+        raw: &'a str,
+    ) -> Result<HashMap<&'a str, GistFile>> {
         let mut files = HashMap::new();
-        files.insert(file_name, GistFile { content: text });
+        if !file_name.is_empty() {
+            files.insert(
+                file_name,
+                GistFile {
+                    content: text.to_string(),
+                },
+            );
+        }
+        for (name, value) in pairs(raw, '=')? {
+            let content = match value.strip_prefix('@') {
+                Some(path) => {
+                    fs::read_to_string(path).with_context(|| format!("Unable to read {}.", path))?
+                }
+                None => value.to_string(),
+            };
+            files.insert(name, GistFile { content });
+        }
+        Ok(files)
+    }
+
+    fn create(
+        client: &Client,
+        api: &str,
+        token: &str,
+        files: HashMap<&str, GistFile>,
+        description: &str,
+        public: bool,
+    ) -> Result<Response> {
+        let gist_payload = GistPayload {
+            description: Some(description),
+            public: Some(public),
+            files,
+        };
+
+        Gist::send(
+            client.post(format!("{}/gists", api)).json(&gist_payload),
+            token,
+        )
+    }
+
+    fn update(
+        client: &Client,
+        api: &str,
+        gist_id: &str,
+        token: &str,
+        files: HashMap<&str, GistFile>,
+    ) -> Result<Response> {
+        let url = format!("{}/gists/{}", api, gist_id);
 
-        let gist_payload = GistPayload { files };
-        let client = Client::new();
+        let gist_payload = GistPayload {
+            description: None,
+            public: None,
+            files,
+        };
 
-        Ok(client
-            .patch(&url)
-            .header("Authorization", format!("token {}", access_token))
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", USER_AGENT)
-            .json(&gist_payload)
-            .send()?)
+        Gist::send(client.patch(url).json(&gist_payload), token)
     }
 
-    fn get(gist_id: &str) -> Result<Response> {
-        let url = format!("https://api.github.com/gists/{}", gist_id);
+    fn get(client: &Client, api: &str, gist_id: &str, token: &str) -> Result<Response> {
+        let url = format!("{}/gists/{}", api, gist_id);
 
-        let client = Client::new();
-        Ok(client
-            .get(&url)
-            .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", USER_AGENT)
-            .send()?)
+        Gist::send(client.get(url), token)
+    }
+
+    // Turns GitHub's answer into the outputs, with the text of file_name if
+    // given.
+    fn result(response: Response, gist_id: &str, file_name: &str) -> Result<Outputs> {
+        let status = response.status();
+        if !status.is_success() {
+            let gist = match gist_id {
+                "" => "the new gist".to_string(),
+                gist_id => format!("gist {}", gist_id),
+            };
+            return Err(match status {
+                StatusCode::NOT_FOUND => {
+                    anyhow!("GitHub has no {} visible to the token.", gist)
+                }
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    anyhow!("The token is not allowed to access {}.", gist)
+                }
+                status => anyhow!("GitHub returned {} for {}.", status, gist),
+            });
+        }
+
+        let mut result = HashMap::new();
+        result.insert(
+            Gist::STATUS_CODE.to_string(),
+            response.status().as_str().to_string(),
+        );
+
+        let content: String = response.text()?;
+        let resp: GistResponse = serde_json::from_str(&content)?;
+
+        if !file_name.is_empty() {
+            let file = resp
+                .files
+                .get(file_name)
+                .ok_or_else(|| anyhow!("Missing {} in gist {}.", file_name, resp.id))?;
+            result.insert(Gist::TEXT.to_string(), file.content.clone());
+        }
+        result.insert(Gist::ID.to_string(), resp.id);
+        result.insert(Gist::HTML_URL.to_string(), resp.html_url);
+
+        Ok(result)
     }
 }
 
 impl Workflow for Gist {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let action: GistAction = input.parameter(Gist::ACTION).to_uppercase().parse()?;
         let gist_id = input.parameter(Gist::GIST_ID);
-        let access_token = input.parameter(Gist::ACCESS_TOKEN);
+        let token = match input.parameter(Gist::TOKEN) {
+            "" => input.parameter(Gist::ACCESS_TOKEN),
+            token => token,
+        };
         let file_name = input.parameter(Gist::FILE_NAME);
         let text = input.parameter(Gist::TEXT);
+        if gist_id.is_empty() && !matches!(action, GistAction::Create) {
+            return Err(anyhow!("Gist requires gist_id unless creating a gist."));
+        }
 
+        let config = ClientConfig::new(
+            input.parameter(Gist::PROXY),
+            input.parse(Gist::TIMEOUT_SECS)?,
+        );
+        let client = Http::client(context, config)?;
         let response = match action {
-            GistAction::GET => Gist::get(gist_id),
-            GistAction::UPDATE => Gist::update(gist_id, access_token, file_name, text),
+            GistAction::Get => Gist::get(&client, Gist::API, gist_id, token),
+            GistAction::Create => Gist::create(
+                &client,
+                Gist::API,
+                token,
+                Gist::files(file_name, text, input.parameter(Gist::FILES))?,
+                input.parameter(Gist::DESCRIPTION),
+                input.flag(Gist::PUBLIC, false)?,
+            ),
+            GistAction::Update => Gist::update(
+                &client,
+                Gist::API,
+                gist_id,
+                token,
+                Gist::files(file_name, text, input.parameter(Gist::FILES))?,
+            ),
         }?;
 
-        let mut result = HashMap::new();
-        result.insert(Gist::STATUS_CODE, response.status().as_str().to_string());
+        Ok(vec![Gist::result(response, gist_id, file_name)?])
+    }
 
-        let content: String = response.text()?;
-        let resp: GistPayload = serde_json::from_str(&content)?;
+    fn parameters(&self) -> &'static [&'static str] {
+        &Gist::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Gist::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Gist::OUTPUT
+    }
+}
 
-        result.insert(Gist::TEXT, resp.files[file_name].content.to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, run, serve_all};
+    use serde_json::{json, Value};
 
-        if let Some(next) = context.next() {
-            next.execute(context, result)?;
-        }
-        Ok(())
+    const GIST: &str = r#"{"id": "abc", "html_url": "https://gist.github.com/abc",
+        "files": {"config.yml": {"content": "workflows: []"}}}"#;
+
+    fn body(request: &str) -> Value {
+        serde_json::from_str(request.split_once("\r\n\r\n").unwrap().1).unwrap()
     }
 
-    fn parameters(&self) -> &'static [&'static str] {
-        return &Gist::PARAMS;
+    fn files(content: &str) -> HashMap<&str, GistFile> {
+        Gist::files("config.yml", content, "").unwrap()
     }
-    fn outputs(&self) -> &'static [&'static str] {
-        return &Gist::OUTPUT;
+
+    #[test]
+    fn test_create() {
+        let (api, requests) = serve_all(vec![response("201 Created", GIST)]);
+        let response =
+            Gist::create(&Client::new(), &api, "t", files("a: 1"), "config", true).unwrap();
+        let outputs = Gist::result(response, "", "").unwrap();
+        assert_eq!(outputs[Gist::STATUS_CODE], "201");
+        assert_eq!(outputs[Gist::ID], "abc");
+        assert_eq!(outputs[Gist::HTML_URL], "https://gist.github.com/abc");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /gists HTTP/1.1"));
+        assert!(request.contains("authorization: token t\r\n"));
+        assert!(request.contains("accept: application/vnd.github.v3+json\r\n"));
+        assert_eq!(
+            body(&request),
+            json!({
+                "description": "config",
+                "public": true,
+                "files": {"config.yml": {"content": "a: 1"}},
+            })
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let (api, requests) = serve_all(vec![response("200 OK", GIST)]);
+        let response = Gist::update(&Client::new(), &api, "abc", "t", files("a: 2")).unwrap();
+        assert_eq!(Gist::result(response, "abc", "").unwrap()[Gist::ID], "abc");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("PATCH /gists/abc HTTP/1.1"));
+        assert_eq!(
+            body(&request),
+            json!({"files": {"config.yml": {"content": "a: 2"}}})
+        );
+    }
+
+    #[test]
+    fn test_get() {
+        let (api, requests) = serve_all(vec![response("200 OK", GIST)]);
+        let response = Gist::get(&Client::new(), &api, "abc", "").unwrap();
+        let outputs = Gist::result(response, "abc", "config.yml").unwrap();
+        assert_eq!(outputs[Gist::TEXT], "workflows: []");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("GET /gists/abc HTTP/1.1"));
+        assert!(!request.contains("authorization:"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |status: &str, gist_id: &str, file_name: &str| {
+            let (api, _) = serve_all(vec![response(status, GIST)]);
+            let response = Gist::get(&Client::new(), &api, gist_id, "t").unwrap();
+            Gist::result(response, gist_id, file_name)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("404 Not Found", "abc", ""),
+            "GitHub has no gist abc visible to the token."
+        );
+        assert_eq!(
+            error("403 Forbidden", "abc", ""),
+            "The token is not allowed to access gist abc."
+        );
+        assert_eq!(
+            error("500 Internal Server Error", "", ""),
+            "GitHub returned 500 Internal Server Error for the new gist."
+        );
+        assert_eq!(
+            error("200 OK", "abc", "other.yml"),
+            "Missing other.yml in gist abc."
+        );
+
+        let error = Gist::get(&Client::new(), "http://127.0.0.1:1", "abc", "t").unwrap_err();
+        assert!(error.to_string().starts_with("Gist request failed: "));
+
+        assert_eq!(
+            run(Gist {}, &[(Gist::ACTION, "update")])
+                .unwrap_err()
+                .to_string(),
+            "Gist requires gist_id unless creating a gist."
+        );
     }
 }
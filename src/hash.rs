@@ -0,0 +1,146 @@
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::{collections::HashMap, fs::File};
+use strum::{Display, EnumString};
+
+pub struct Hash {}
+
+#[derive(Debug, Clone, Copy, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Algorithm {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Algorithm {
+    // Streams the reader through the hash, so large files are never held in
+    // memory, and returns the lowercase hex digest.
+    pub fn digest<R: Read>(self, reader: &mut R) -> io::Result<String> {
+        fn digest<D: Digest + Write, R: Read>(mut hasher: D, reader: &mut R) -> io::Result<String> {
+            io::copy(reader, &mut hasher)?;
+            Ok(hex(&hasher.finalize()))
+        }
+        match self {
+            Algorithm::Sha256 => digest(Sha256::new(), reader),
+            Algorithm::Sha1 => digest(Sha1::new(), reader),
+            Algorithm::Md5 => digest(Md5::new(), reader),
+        }
+    }
+}
+
+impl Hash {
+    // Input
+    const PATH: &'static str = "path";
+    // `sha256` (default), `sha1` or `md5`.
+    const ALGORITHM: &'static str = "algorithm";
+    // Fails the step unless the digest matches, ignoring case.
+    const EXPECTED: &'static str = "expected";
+    const PARAMS: [&'static str; 3] = [Hash::PATH, Hash::ALGORITHM, Hash::EXPECTED];
+    const REQUIRED: [&'static str; 1] = [Hash::PATH];
+
+    // Output
+    const HASH: &'static str = "hash";
+    const OUTPUT: [&'static str; 2] = [Hash::HASH, Hash::PATH];
+}
+
+impl Workflow for Hash {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let path = input.parameter(Hash::PATH);
+        let algorithm = match input.parameter(Hash::ALGORITHM) {
+            "" => Algorithm::Sha256,
+            algorithm => algorithm
+                .to_lowercase()
+                .parse()
+                .map_err(|_| anyhow!("Unknown algorithm {}.", algorithm))?,
+        };
+        let expected = input.parameter(Hash::EXPECTED).trim();
+
+        let mut file = File::open(path).with_context(|| format!("Unable to read {}.", path))?;
+        let hash = algorithm.digest(&mut file)?;
+        if !expected.is_empty() && !expected.eq_ignore_ascii_case(&hash) {
+            return Err(anyhow!(
+                "The {} of {} is {}, but {} is expected.",
+                algorithm,
+                path,
+                hash,
+                expected
+            ));
+        }
+
+        let mut result = HashMap::new();
+        result.insert(Hash::HASH.to_string(), hash);
+        result.insert(Hash::PATH.to_string(), path.to_string());
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Hash::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Hash::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Hash::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+    use std::fs;
+
+    #[test]
+    fn test_hash() {
+        let path = scratch("hash").join("input");
+        fs::write(&path, "abc").unwrap();
+        let path = path.to_str().unwrap();
+
+        let hash = |algorithm| {
+            run(Hash {}, &[(Hash::PATH, path), (Hash::ALGORITHM, algorithm)]).unwrap()[0]
+                [Hash::HASH]
+                .clone()
+        };
+        assert_eq!(
+            hash(""),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash("SHA1"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hash("md5"), "900150983cd24fb0d6963f7d28e17f72");
+
+        run(
+            Hash {},
+            &[
+                (Hash::PATH, path),
+                (Hash::ALGORITHM, "md5"),
+                (Hash::EXPECTED, "900150983CD24FB0D6963F7D28E17F72"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            run(
+                Hash {},
+                &[
+                    (Hash::PATH, path),
+                    (Hash::ALGORITHM, "md5"),
+                    (Hash::EXPECTED, "0")
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            format!(
+                "The md5 of {} is 900150983cd24fb0d6963f7d28e17f72, but 0 is expected.",
+                path
+            )
+        );
+    }
+}
@@ -1,46 +1,760 @@
-use crate::{Context, Input, Inputs, Workflow};
-use anyhow::Result;
-use reqwest::blocking::{Client, Request};
-use std::collections::HashMap;
+use crate::{
+    util::{lookup, pairs, render},
+    Context, Input, Inputs, Outputs, Workflow, USER_AGENT,
+};
+use anyhow::{anyhow, Context as _, Result};
+use reqwest::{
+    blocking::{multipart::Form, Client, Response},
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    redirect::Policy,
+    Proxy,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read as _},
+    thread,
+    time::Duration,
+};
 
 pub struct Http {}
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientConfig {
+    proxy: String,
+    user_agent: String,
+    // `None` keeps reqwest's 30 second default.
+    timeout: Option<u64>,
+    cookie_jar: bool,
+    follow_redirects: bool,
+    max_redirects: Option<usize>,
+}
+
+impl ClientConfig {
+    // Http's defaults, for the other workflows sending requests.
+    pub fn new(proxy: &str, timeout: Option<u64>) -> Self {
+        Self {
+            proxy: proxy.to_string(),
+            user_agent: USER_AGENT.to_string(),
+            timeout,
+            cookie_jar: false,
+            follow_redirects: true,
+            max_redirects: None,
+        }
+    }
+
+    fn build(&self, context: &Context) -> Result<Client> {
+        let mut builder = Client::builder().no_proxy().user_agent(&self.user_agent);
+        for proxy in Http::proxies(&self.proxy, &context.env)? {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(secs) = self.timeout {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+        if self.cookie_jar {
+            builder = builder.cookie_provider(context.cookies.clone());
+        }
+        if !self.follow_redirects {
+            builder = builder.redirect(Policy::none());
+        } else if let Some(max) = self.max_redirects {
+            // reqwest counts the original url among the redirects.
+            builder = builder.redirect(Policy::limited(max.saturating_add(1)));
+        }
+        Ok(builder.build()?)
+    }
+}
+
 impl Http {
     // Input
     const URL: &'static str = "url";
     const METHOD: &'static str = "method";
-    const PARAMS: [&'static str; 2] = [Http::URL, Http::METHOD];
+    const BODY: &'static str = "body";
+    const HEADERS: &'static str = "headers";
+    // Defaults to reqwest's 30 second timeout, so slow endpoints fail; set
+    // timeout_secs to raise it.
+    const TIMEOUT_SECS: &'static str = "timeout_secs";
+    const RETRIES: &'static str = "retries";
+    const RETRY_BACKOFF_MS: &'static str = "retry_backoff_ms";
+    // Defaults to HTTP_PROXY/HTTPS_PROXY from the environment.
+    const PROXY: &'static str = "proxy";
+    const BASIC_AUTH_USER: &'static str = "basic_auth_user";
+    const BASIC_AUTH_PASS: &'static str = "basic_auth_pass";
+    const BEARER_TOKEN: &'static str = "bearer_token";
+    // One `key=value` pair per line, appended to any query string in the url.
+    const QUERY: &'static str = "query";
+    // One `key=value` pair per line, sent urlencoded in place of body.
+    const FORM: &'static str = "form";
+    const JSON: &'static str = "json";
+    const FOLLOW_REDIRECTS: &'static str = "follow_redirects";
+    const MAX_REDIRECTS: &'static str = "max_redirects";
+    // Streams the body to this path and outputs it in place of text.
+    const OUTPUT_FILE: &'static str = "output_file";
+    // A dotted path into the JSON body, e.g. `data.items.0.id`.
+    const EXTRACT: &'static str = "extract";
+    // Keeps cookies across the Http steps of a run that enable it.
+    const COOKIE_JAR: &'static str = "cookie_jar";
+    // One `name=value` field per line, `name=@path` attaches a file.
+    const MULTIPART: &'static str = "multipart";
+    const FAIL_ON_ERROR: &'static str = "fail_on_error";
+    // Larger bodies fail the step, or are cut off with truncate_body.
+    const MAX_BODY_BYTES: &'static str = "max_body_bytes";
+    const TRUNCATE_BODY: &'static str = "truncate_body";
+    // Defaults to the crate's USER_AGENT.
+    const USER_AGENT: &'static str = "user_agent";
+    const PARAMS: [&'static str; 24] = [
+        Http::URL,
+        Http::METHOD,
+        Http::BODY,
+        Http::HEADERS,
+        Http::TIMEOUT_SECS,
+        Http::RETRIES,
+        Http::RETRY_BACKOFF_MS,
+        Http::PROXY,
+        Http::BASIC_AUTH_USER,
+        Http::BASIC_AUTH_PASS,
+        Http::BEARER_TOKEN,
+        Http::QUERY,
+        Http::FORM,
+        Http::JSON,
+        Http::FOLLOW_REDIRECTS,
+        Http::MAX_REDIRECTS,
+        Http::OUTPUT_FILE,
+        Http::EXTRACT,
+        Http::COOKIE_JAR,
+        Http::MULTIPART,
+        Http::FAIL_ON_ERROR,
+        Http::MAX_BODY_BYTES,
+        Http::TRUNCATE_BODY,
+        Http::USER_AGENT,
+    ];
+    const REQUIRED: [&'static str; 1] = [Http::URL];
+
+    const DEFAULT_RETRY_BACKOFF_MS: u64 = 1000;
+    // How much of the body a fail_on_error message quotes.
+    const ERROR_SNIPPET_CHARS: usize = 200;
 
     // Output
     const STATUS_CODE: &'static str = "status_code";
     const TEXT: &'static str = "text";
-    const OUTPUT: [&'static str; 2] = [Http::STATUS_CODE, Http::TEXT];
+    // The redirect target when follow_redirects is false.
+    const LOCATION: &'static str = "location";
+    // Every response header also comes out as `header_<lower-cased name>`,
+    // values joined by commas. Those keys depend on the response, so they are
+    // left out of OUTPUT.
+    const HEADER_PREFIX: &'static str = "header_";
+    const EXTRACTED: &'static str = "extracted";
+    const OUTPUT: [&'static str; 5] = [
+        Http::STATUS_CODE,
+        Http::TEXT,
+        Http::LOCATION,
+        Http::OUTPUT_FILE,
+        Http::EXTRACTED,
+    ];
+
+    // Headers are given as `Name: Value` lines and override the defaults.
+    fn headers(raw: &str, bearer_token: &str) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        if !bearer_token.is_empty() {
+            let value = HeaderValue::from_str(&format!("Bearer {}", bearer_token))
+                .context("Invalid bearer_token.")?;
+            headers.insert(header::AUTHORIZATION, value);
+        }
+        for (name, value) in pairs(raw, ':')? {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {}.", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {}.", name))?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+
+    fn multipart(raw: &str) -> Result<Option<Form>> {
+        let fields = pairs(raw, '=')?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        let mut form = Form::new();
+        for (name, value) in fields {
+            form = match value.strip_prefix('@') {
+                Some(path) => form
+                    .file(name.to_string(), path)
+                    .with_context(|| format!("Unable to attach {}.", path))?,
+                None => form.text(name.to_string(), value.to_string()),
+            };
+        }
+        Ok(Some(form))
+    }
+
+    fn text(response: Response, max_bytes: Option<u64>, truncate: bool) -> Result<String> {
+        let max_bytes = match max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok(response.text()?),
+        };
+        let mut body = Vec::new();
+        response.take(max_bytes + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > max_bytes {
+            if !truncate {
+                return Err(anyhow!(
+                    "Response body exceeds max_body_bytes {}.",
+                    max_bytes
+                ));
+            }
+            body.truncate(max_bytes as usize);
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    // Clients are cached on the context to keep connections alive between steps.
+    pub fn client(context: &mut Context, config: ClientConfig) -> Result<Client> {
+        if let Some(client) = context.clients.get(&config) {
+            return Ok(client.clone());
+        }
+        let client = config.build(context)?;
+        context.clients.insert(config, client.clone());
+        Ok(client)
+    }
+
+    pub fn proxies(proxy: &str, env: &HashMap<String, String>) -> Result<Vec<Proxy>> {
+        let proxies = if proxy.is_empty() {
+            let http = env.get("HTTP_PROXY").map(Proxy::http);
+            let https = env.get("HTTPS_PROXY").map(Proxy::https);
+            http.into_iter()
+                .chain(https)
+                .collect::<Result<_, _>>()
+                .context("Invalid HTTP_PROXY or HTTPS_PROXY.")?
+        } else {
+            vec![Proxy::all(proxy).with_context(|| format!("Invalid proxy {}.", proxy))?]
+        };
+        Ok(proxies)
+    }
 }
 
 impl Workflow for Http {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let url = input.parameter(Http::URL);
         let method = input.parameter(Http::METHOD);
+        let body = input.parameter(Http::BODY);
+        let user = input.parameter(Http::BASIC_AUTH_USER);
+        let pass = input.parameter(Http::BASIC_AUTH_PASS);
+        let query = pairs(input.parameter(Http::QUERY), '=')?;
+        let form = pairs(input.parameter(Http::FORM), '=')?;
+        let json: Option<Value> = match input.parameter(Http::JSON) {
+            "" => None,
+            json => Some(serde_json::from_str(json).map_err(|e| anyhow!("Invalid json: {}.", e))?),
+        };
+        let multipart = Http::multipart(input.parameter(Http::MULTIPART))?;
+        let bodies = [Http::BODY, Http::FORM, Http::JSON, Http::MULTIPART];
+        if bodies
+            .iter()
+            .filter(|key| !input.parameter(key).is_empty())
+            .count()
+            > 1
+        {
+            return Err(anyhow!(
+                "Http accepts only one of body, form, json and multipart."
+            ));
+        }
+        let headers = Http::headers(
+            input.parameter(Http::HEADERS),
+            input.parameter(Http::BEARER_TOKEN),
+        )?;
+        let retries: u32 = input.parse(Http::RETRIES)?.unwrap_or(0);
+        let backoff = input
+            .parse(Http::RETRY_BACKOFF_MS)?
+            .unwrap_or(Http::DEFAULT_RETRY_BACKOFF_MS);
+        let fail_on_error = input.flag(Http::FAIL_ON_ERROR, false)?;
+        let max_body_bytes = input.parse(Http::MAX_BODY_BYTES)?;
+        let truncate_body = input.flag(Http::TRUNCATE_BODY, false)?;
+        let output_file = input.parameter(Http::OUTPUT_FILE);
+        let extract = input.parameter(Http::EXTRACT);
+        if !output_file.is_empty() && !extract.is_empty() {
+            return Err(anyhow!(
+                "Http cannot extract from a body saved to output_file."
+            ));
+        }
+        let config = ClientConfig {
+            proxy: input.parameter(Http::PROXY).to_string(),
+            user_agent: match input.parameter(Http::USER_AGENT) {
+                "" => USER_AGENT.to_string(),
+                user_agent => user_agent.to_string(),
+            },
+            timeout: input.parse(Http::TIMEOUT_SECS)?,
+            cookie_jar: input.flag(Http::COOKIE_JAR, false)?,
+            follow_redirects: input.flag(Http::FOLLOW_REDIRECTS, true)?,
+            max_redirects: input.parse(Http::MAX_REDIRECTS)?,
+        };
 
-        reqwest::Proxy::all("http://127.0.0.1:7890")?;
-        let client = Client::new();
-        let request = Request::new(method.parse()?, url.parse()?);
-        let response = client.execute(request)?;
+        let client = Http::client(context, config)?;
+        let mut request = client.request(method.parse()?, url).headers(headers);
+        if !query.is_empty() {
+            request = request.query(&query);
+        }
+        if !body.is_empty() {
+            request = request.body(body.to_string());
+        }
+        if !form.is_empty() {
+            request = request.form(&form);
+        }
+        if let Some(json) = json {
+            request = request.json(&json);
+        }
+        if let Some(multipart) = multipart {
+            request = request.multipart(multipart);
+        }
+        if !user.is_empty() {
+            request = request.basic_auth(user, Some(pass).filter(|pass| !pass.is_empty()));
+        }
 
-        let mut result = HashMap::new();
-        result.insert(Http::STATUS_CODE, response.status().as_str().to_string());
-        result.insert(Http::TEXT, response.text()?);
+        // Connection failures and 5xx responses are retried with exponential backoff.
+        // Streaming bodies such as multipart cannot be cloned and get a single attempt.
+        let mut attempt = 0;
+        let mut response = loop {
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < retries => retry,
+                _ => break request.send()?,
+            };
+            match retry.send() {
+                Ok(response) if !response.status().is_server_error() => break response,
+                _ => {}
+            }
+            thread::sleep(Duration::from_millis(
+                backoff.saturating_mul(2u64.saturating_pow(attempt)),
+            ));
+            attempt += 1;
+        };
 
-        if let Some(next) = context.next() {
-            next.execute(context, result)?;
+        let status = response.status();
+        if fail_on_error && (status.is_client_error() || status.is_server_error()) {
+            let text = response.text().unwrap_or_default();
+            let snippet: String = text.chars().take(Http::ERROR_SNIPPET_CHARS).collect();
+            return Err(anyhow!("Request failed with {}: {}", status, snippet));
         }
-        Ok(())
+
+        let mut result = HashMap::new();
+        result.insert(Http::STATUS_CODE.to_string(), status.as_str().to_string());
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+        result.insert(Http::LOCATION.to_string(), location.to_string());
+        for name in response.headers().keys() {
+            let values = response
+                .headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ");
+            result.insert(format!("{}{}", Http::HEADER_PREFIX, name), values);
+        }
+        if output_file.is_empty() {
+            let text = Http::text(response, max_body_bytes, truncate_body)?;
+            if !extract.is_empty() {
+                let json = serde_json::from_str(&text).context("Response is not valid json.")?;
+                result.insert(Http::EXTRACTED.to_string(), render(lookup(&json, extract)?));
+            }
+            result.insert(Http::TEXT.to_string(), text);
+        } else {
+            let mut file = File::create(output_file)
+                .with_context(|| format!("Unable to create {}.", output_file))?;
+            io::copy(&mut response, &mut file)?;
+            result.insert(Http::OUTPUT_FILE.to_string(), output_file.to_string());
+        }
+
+        Ok(vec![result])
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Http::PARAMS;
+        &Http::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Http::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Http::OUTPUT;
+        &Http::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{context, read_request, response, run, run_in, serve_all};
+    use std::{
+        io::Write,
+        net::TcpListener,
+        sync::mpsc::Receiver,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    // Answers every request with an empty 200 on kept-alive connections and counts them.
+    fn serve_keep_alive() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    while read_request(&mut stream).is_some() {
+                        let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[test]
+    fn test_request_goes_through_proxy() {
+        let (proxy, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, "http://example.invalid/path"),
+                (Http::METHOD, "GET"),
+                (Http::PROXY, &proxy),
+            ],
+        )
+        .unwrap();
+
+        assert!(server
+            .recv()
+            .unwrap()
+            .starts_with("GET http://example.invalid/path HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_query_is_merged_and_encoded() {
+        let (address, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, &format!("{}/path?a=1", address)),
+                (Http::METHOD, "GET"),
+                (Http::QUERY, "b = x&y\nc=d e"),
+            ],
+        )
+        .unwrap();
+
+        assert!(server
+            .recv()
+            .unwrap()
+            .starts_with("GET /path?a=1&b=x%26y&c=d+e HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_form() {
+        let (address, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::FORM, "a=b&c=d\ne=1"),
+            ],
+        )
+        .unwrap();
+
+        let request = server.recv().unwrap();
+        assert!(request.contains("content-type: application/x-www-form-urlencoded"));
+        assert!(request.ends_with("\r\n\r\na=b%26c%3Dd&e=1"));
+
+        assert!(run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::BODY, "raw"),
+                (Http::FORM, "a=b"),
+            ]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_cookie_jar() {
+        let mut context = context();
+        let (address, _) = serve_all(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nSet-Cookie: session=abc\r\n\r\n",
+        ]);
+        let parameters = [(Http::METHOD, "GET"), (Http::COOKIE_JAR, "true")];
+        run_in(
+            &mut context,
+            Http {},
+            &[&parameters[..], &[(Http::URL, &address)]].concat(),
+        )
+        .unwrap();
+
+        let (address, server) = serve_all(vec![OK]);
+        run_in(
+            &mut context,
+            Http {},
+            &[&parameters[..], &[(Http::URL, &address)]].concat(),
+        )
+        .unwrap();
+        assert!(server.recv().unwrap().contains("cookie: session=abc"));
+    }
+
+    #[test]
+    fn test_json() {
+        let (address, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::JSON, r#"{"name": "ci", "tags": [1, 2]}"#),
+            ],
+        )
+        .unwrap();
+
+        let request = server.recv().unwrap();
+        assert!(request.contains("content-type: application/json\r\n"));
+        assert!(request.ends_with(r#"{"name":"ci","tags":[1,2]}"#));
+
+        let error = run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::JSON, "{\"name\": }"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid json: expected value at line 1 column 10."
+        );
+    }
+
+    #[test]
+    fn test_multipart() {
+        let (address, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::MULTIPART, "name=value\nfile=@Cargo.toml"),
+            ],
+        )
+        .unwrap();
+
+        let request = server.recv().unwrap();
+        assert!(request.contains("content-type: multipart/form-data"));
+        assert!(request.contains("name=\"file\"; filename=\"Cargo.toml\""));
+
+        let error = run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "POST"),
+                (Http::MULTIPART, "file=@missing.txt"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Unable to attach missing.txt.");
+    }
+
+    #[test]
+    fn test_fail_on_error() {
+        let (address, _) = serve_all(vec![
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 7\r\n\r\nmissing",
+        ]);
+        let error = run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "GET"),
+                (Http::FAIL_ON_ERROR, "true"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Request failed with 404 Not Found: missing"
+        );
+    }
+
+    #[test]
+    fn test_max_body_bytes() {
+        let (address, _) = serve_all(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world",
+        ]);
+        let error = run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "GET"),
+                (Http::MAX_BODY_BYTES, "5"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Response body exceeds max_body_bytes 5.");
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let (address, _) = serve_all(vec![
+            "HTTP/1.1 200 OK\r\nX-Tag: a\r\nX-Tag: b\r\nContent-Length: 0\r\n\r\n",
+        ]);
+        let outputs = run(Http {}, &[(Http::URL, &address), (Http::METHOD, "GET")]).unwrap();
+        assert_eq!(outputs[0]["header_x-tag"], "a, b");
+        assert!(!Http {}.outputs().iter().any(|key| key.contains('*')));
+    }
+
+    #[test]
+    fn test_user_agent() {
+        let (address, server) = serve_all(vec![OK]);
+        run(Http {}, &[(Http::URL, &address), (Http::METHOD, "GET")]).unwrap();
+        assert!(server
+            .recv()
+            .unwrap()
+            .contains("user-agent: workflows/1.0\r\n"));
+
+        let (address, server) = serve_all(vec![OK]);
+        run(
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "GET"),
+                (Http::USER_AGENT, "Mozilla/5.0"),
+            ],
+        )
+        .unwrap();
+        assert!(server
+            .recv()
+            .unwrap()
+            .contains("user-agent: Mozilla/5.0\r\n"));
+    }
+
+    #[test]
+    fn test_redirects() {
+        const REDIRECT: &str =
+            "HTTP/1.1 302 Found\r\nLocation: /next\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let get = |responses: Vec<&str>, extra: &[(&'static str, &str)]| {
+            let (address, _) = serve_all(responses);
+            let mut parameters = vec![(Http::URL, address.as_str()), (Http::METHOD, "GET")];
+            parameters.extend_from_slice(extra);
+            run(Http {}, &parameters)
+        };
+
+        let outputs = get(vec![REDIRECT, OK], &[]).unwrap();
+        assert_eq!(outputs[0][Http::STATUS_CODE], "200");
+        assert_eq!(outputs[0][Http::LOCATION], "");
+
+        let outputs = get(vec![REDIRECT, OK], &[(Http::FOLLOW_REDIRECTS, "false")]).unwrap();
+        assert_eq!(outputs[0][Http::STATUS_CODE], "302");
+        assert_eq!(outputs[0][Http::LOCATION], "/next");
+
+        let outputs = get(vec![REDIRECT, OK], &[(Http::MAX_REDIRECTS, "1")]).unwrap();
+        assert_eq!(outputs[0][Http::STATUS_CODE], "200");
+        let error = get(vec![REDIRECT, REDIRECT, OK], &[(Http::MAX_REDIRECTS, "1")]).unwrap_err();
+        assert!(format!("{:#}", error).contains("too many redirects"));
+    }
+
+    // Counts the requests a server received, waiting briefly for stragglers.
+    fn received(server: &Receiver<String>) -> usize {
+        std::iter::from_fn(|| server.recv_timeout(Duration::from_millis(200)).ok()).count()
+    }
+
+    #[test]
+    fn test_retries() {
+        let attempt = |first: &str, extra: &[(&'static str, &str)]| {
+            let (address, server) = serve_all(vec![response(first, ""), response("200 OK", "")]);
+            let mut parameters = vec![
+                (Http::URL, address.as_str()),
+                (Http::METHOD, "POST"),
+                (Http::RETRY_BACKOFF_MS, "1"),
+            ];
+            parameters.extend_from_slice(extra);
+            let outputs = run(Http {}, &parameters).unwrap();
+            (outputs[0][Http::STATUS_CODE].clone(), received(&server))
+        };
+
+        let retries = [(Http::RETRIES, "2")];
+        assert_eq!(
+            attempt("500 Internal Server Error", &retries),
+            ("200".to_string(), 2)
+        );
+        assert_eq!(attempt("404 Not Found", &retries), ("404".to_string(), 1));
+        assert_eq!(
+            attempt("500 Internal Server Error", &[(Http::RETRIES, "0")]),
+            ("500".to_string(), 1)
+        );
+        assert_eq!(
+            attempt(
+                "500 Internal Server Error",
+                &[(Http::RETRIES, "2"), (Http::MULTIPART, "a=b")]
+            ),
+            ("500".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn test_client_is_reused() {
+        let (address, connections) = serve_keep_alive();
+        let mut context = context();
+        for _ in 0..10 {
+            run_in(
+                &mut context,
+                Http {},
+                &[(Http::URL, &address), (Http::METHOD, "GET")],
+            )
+            .unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(context.clients.len(), 1);
+
+        run_in(
+            &mut context,
+            Http {},
+            &[
+                (Http::URL, &address),
+                (Http::METHOD, "GET"),
+                (Http::TIMEOUT_SECS, "5"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(context.clients.len(), 2);
+    }
+
+    // Run with `cargo test -- --ignored --nocapture bench_client_reuse`.
+    #[test]
+    #[ignore]
+    fn bench_client_reuse() {
+        let (address, _) = serve_keep_alive();
+        let parameters = [(Http::URL, &address[..]), (Http::METHOD, "GET")];
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            run(Http {}, &parameters).unwrap();
+        }
+        let fresh = start.elapsed();
+
+        let mut context = context();
+        let start = Instant::now();
+        for _ in 0..10 {
+            run_in(&mut context, Http {}, &parameters).unwrap();
+        }
+        let shared = start.elapsed();
+
+        println!(
+            "10 calls: fresh clients {:?}, shared client {:?}",
+            fresh, shared
+        );
     }
 }
@@ -0,0 +1,107 @@
+use crate::{
+    util::{lookup, render},
+    Context, Input, Inputs, Outputs, Workflow,
+};
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub struct Json {}
+
+impl Json {
+    // Input
+    const INPUT: &'static str = "input";
+    // A dotted path such as `data.items.0.id`; empty takes the whole document.
+    const PATH: &'static str = "path";
+    // Stands in for a path the document lacks, which fails the step otherwise.
+    const DEFAULT: &'static str = "default";
+    const PARAMS: [&'static str; 3] = [Json::INPUT, Json::PATH, Json::DEFAULT];
+    const REQUIRED: [&'static str; 1] = [Json::INPUT];
+
+    // Output
+    // Strings come out without quotes, everything else as JSON.
+    const VALUE: &'static str = "value";
+    const OUTPUT: [&'static str; 1] = [Json::VALUE];
+}
+
+impl Workflow for Json {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let json: Value = serde_json::from_str(input.parameter(Json::INPUT))
+            .context("Unable to parse the input as JSON.")?;
+        let path = input.parameter(Json::PATH);
+
+        let value = match (lookup(&json, path), input.get(Json::DEFAULT)) {
+            (Ok(value), _) => render(value),
+            (Err(_), Some(default)) => default.clone(),
+            (Err(e), None) => return Err(e),
+        };
+
+        let mut result = HashMap::new();
+        result.insert(Json::VALUE.to_string(), value);
+
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Json::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Json::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Json::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run;
+
+    const RELEASE: &str =
+        r#"{"tag_name": "v1.2.0", "assets": [{"name": "app.tar.gz", "size": 1024}]}"#;
+
+    fn value(parameters: &[(&'static str, &str)]) -> Result<String> {
+        let mut input = vec![(Json::INPUT, RELEASE)];
+        input.extend_from_slice(parameters);
+        Ok(run(Json {}, &input)?[0][Json::VALUE].clone())
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(value(&[(Json::PATH, "tag_name")]).unwrap(), "v1.2.0");
+        assert_eq!(value(&[(Json::PATH, "assets.0.size")]).unwrap(), "1024");
+        assert_eq!(
+            value(&[(Json::PATH, "assets.0")]).unwrap(),
+            r#"{"name":"app.tar.gz","size":1024}"#
+        );
+        assert_eq!(
+            value(&[]).unwrap(),
+            serde_json::from_str::<Value>(RELEASE).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(
+            value(&[(Json::PATH, "assets.1.name")])
+                .unwrap_err()
+                .to_string(),
+            "Missing 1 in path assets.1.name."
+        );
+        assert_eq!(
+            value(&[(Json::PATH, "assets.1.name"), (Json::DEFAULT, "none")]).unwrap(),
+            "none"
+        );
+        assert_eq!(
+            value(&[(Json::PATH, "body"), (Json::DEFAULT, "")]).unwrap(),
+            ""
+        );
+        assert_eq!(
+            run(Json {}, &[(Json::INPUT, "<html>")])
+                .unwrap_err()
+                .to_string(),
+            "Unable to parse the input as JSON."
+        );
+    }
+}
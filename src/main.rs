@@ -1,47 +1,109 @@
+mod args;
 mod atom;
 mod command;
+mod copy;
 mod decompress;
+mod delete;
+mod download;
 mod echo;
 mod gist;
+mod hash;
 mod http;
+mod json;
 mod parser;
 mod read;
+mod regex;
 mod rss;
 mod save;
+mod sleep;
+mod template;
+#[cfg(test)]
+mod test_util;
 mod util;
+mod webhook;
 mod wechat;
 
+use crate::args::Args;
 use crate::atom::Atom;
 use crate::command::Command;
+use crate::copy::Copy;
 use crate::decompress::Decompress;
+use crate::delete::Delete;
+use crate::download::Download;
 use crate::echo::Echo;
 use crate::gist::Gist;
-use crate::http::Http;
+use crate::hash::Hash;
+use crate::http::{ClientConfig, Http};
+use crate::json::Json;
 use crate::read::Read;
+use crate::regex::Regex;
 use crate::rss::Rss;
 use crate::save::Save;
+use crate::sleep::Sleep;
+use crate::template::Template;
+use crate::webhook::Webhook;
 use crate::wechat::WeChat;
 use anyhow::{anyhow, Context as _, Result};
 use enum_dispatch::enum_dispatch;
 use lazy_static::lazy_static;
-use parser::fulfill;
+use log::{debug, info, warn, LevelFilter};
+use parser::{check, condition, fulfill};
+use reqwest::{blocking::Client, cookie::Jar};
 use serde::Deserialize;
-use std::{collections::HashMap, env, fs};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    error::Error,
+    fs,
+    io::{self, Read as _},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use util::{boolean, dotenv, mask};
 
-const USER_AGENT: &'static str = "workflows/1.0";
+const USER_AGENT: &str = "workflows/1.0";
 
 #[enum_dispatch(SupportedWorkflows)]
 trait Workflow {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()>;
+    // Returns the outputs to run the following steps with, once each. Feeds
+    // return one per entry and may return none.
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>>;
     fn parameters(&self) -> &'static [&'static str];
+    // The parameters a step must set. The rest fall back to a default.
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &[]
+    }
     fn outputs(&self) -> &'static [&'static str];
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Context {
-    state: usize,
     config: Config,
     env: HashMap<String, String>,
+    // Shared by Http steps that opt into cookie_jar.
+    cookies: Arc<Jar>,
+    clients: HashMap<ClientConfig, Client>,
+    // What the previous step handed to the running one.
+    input: Outputs,
+    // The latest outputs of every named step.
+    steps: HashMap<String, Outputs>,
+    // The outputs that led to the running step, oldest first, each with the
+    // name or type of the step returning it. A step holds one entry however
+    // many outputs it returns, so this never outgrows the steps. Once the run
+    // ends it holds the last path through them.
+    history: Vec<(String, Outputs)>,
+    // The item a foreach step is running for.
+    item: Option<String>,
+    // Steps print their filled parameters instead of running.
+    dry_run: bool,
+    // The outputs reaching the end of the steps, once per run through them.
+    results: Vec<Outputs>,
+    // The config's vars, filled.
+    vars: HashMap<String, String>,
 }
 
 impl Context {
@@ -49,26 +111,107 @@ impl Context {
         let env: HashMap<String, String> = env::vars().collect();
 
         Self {
-            state: 0,
             config,
             env,
+            cookies: Arc::default(),
+            clients: HashMap::new(),
+            input: HashMap::new(),
+            steps: HashMap::new(),
+            history: Vec::new(),
+            item: None,
+            dry_run: false,
+            results: Vec::new(),
+            vars: HashMap::new(),
+        }
+    }
+
+    fn fill_vars(&mut self) -> Result<()> {
+        let mut vars = HashMap::new();
+        for (name, raw) in &self.config.vars {
+            let value = fulfill(raw, &HashMap::new(), self)
+                .with_context(|| format!("Unable to fill var {}.", name))?;
+            vars.insert(name.clone(), value);
         }
+        self.vars = vars;
+        Ok(())
     }
 
-    fn next(&mut self) -> Option<WorkflowConfig> {
-        if self.config.workflows.is_empty() {
-            None
+    fn mask<'a>(&self, key: &str, value: &'a str) -> &'a str {
+        if self.config.mask_secrets {
+            mask(key, value)
         } else {
-            Some(self.config.workflows.remove(0))
+            value
+        }
+    }
+
+    // Sorted by name for printing.
+    fn masked<'a, K: AsRef<str> + Ord>(
+        &self,
+        values: &'a HashMap<K, String>,
+    ) -> BTreeMap<&'a K, &'a str> {
+        values
+            .iter()
+            .map(|(key, value)| (key, self.mask(key.as_ref(), value)))
+            .collect()
+    }
+
+    // Writes every result as a JSON array of objects.
+    fn write_results(&self, destination: &str) -> Result<()> {
+        let results: Vec<_> = self
+            .results
+            .iter()
+            .map(|result| self.masked(result))
+            .collect();
+        let json = serde_json::to_string_pretty(&results)?;
+        match destination {
+            "-" => println!("{}", json),
+            path => fs::write(path, json + "\n")
+                .with_context(|| format!("Unable to write {}.", path))?,
         }
+        Ok(())
+    }
+
+    // Variables already in the real environment win over the file.
+    fn load_env_file(&mut self, path: &str) -> Result<()> {
+        let raw = fs::read_to_string(path).with_context(|| format!("Unable to read {}.", path))?;
+        for (key, value) in dotenv(&raw).with_context(|| format!("Invalid env file {}.", path))? {
+            self.env
+                .entry(key.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        Ok(())
     }
 }
 
-type Outputs = HashMap<&'static str, String>;
+type Outputs = HashMap<String, String>;
 type Inputs = HashMap<&'static str, String>;
 
 trait Input {
     fn parameter(&self, key: &'static str) -> &str;
+
+    // Parses a parameter, treating a missing or empty one as `None`.
+    fn parse<T>(&self, key: &'static str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Error + Send + Sync + 'static,
+    {
+        match self.parameter(key) {
+            "" => Ok(None),
+            value => value
+                .parse()
+                .map(Some)
+                .with_context(|| format!("Invalid {} {}.", key, value)),
+        }
+    }
+
+    // Parses a flag with `util::boolean`, treating a missing or empty one as
+    // `default`.
+    fn flag(&self, key: &'static str, default: bool) -> Result<bool> {
+        match self.parameter(key) {
+            "" => Ok(default),
+            value => boolean(value).with_context(|| format!("Invalid {} {}.", key, value)),
+        }
+    }
 }
 
 impl Input for Inputs {
@@ -89,6 +232,15 @@ enum SupportedWorkflows {
     Atom,
     Read,
     Rss,
+    Sleep,
+    Delete,
+    Copy,
+    Template,
+    Hash,
+    Download,
+    Webhook,
+    Regex,
+    Json,
 }
 
 lazy_static! {
@@ -100,33 +252,464 @@ lazy_static! {
         m.insert("gist", Gist {}.into());
         m.insert("command", Command {}.into());
         m.insert("save", Save {}.into());
+        m.insert("filewrite", Save {}.into());
         m.insert("decompress", Decompress {}.into());
         m.insert("atom", Atom {}.into());
         m.insert("read", Read {}.into());
+        m.insert("fileread", Read {}.into());
         m.insert("rss", Rss {}.into());
+        m.insert("sleep", Sleep {}.into());
+        m.insert("delete", Delete {}.into());
+        m.insert("copy", Copy {}.into());
+        m.insert("template", Template {}.into());
+        m.insert("hash", Hash {}.into());
+        m.insert("download", Download {}.into());
+        m.insert("webhook", Webhook {}.into());
+        m.insert("regex", Regex {}.into());
+        m.insert("json", Json {}.into());
         m
     };
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct Config {
+    // Fail on missing template variables rather than leaving them empty.
+    #[serde(default = "enabled")]
+    strict_templates: bool,
+    // Hide credential-like values wherever outputs or parameters are printed.
+    #[serde(default = "enabled")]
+    mask_secrets: bool,
+    // Where the final outputs go as JSON, `-` for stdout. --output overrides it.
+    output: Option<String>,
+    // Values for `{vars.name}`, filled once against the environment. Each
+    // `--set name=value` replaces or adds one.
+    #[serde(default)]
+    vars: HashMap<String, String>,
     workflows: Vec<WorkflowConfig>,
 }
-#[derive(Debug, Deserialize)]
+
+fn enabled() -> bool {
+    true
+}
+
+impl Config {
+    // Reads a config, replacing every `include: path` step with the steps of
+    // that file, found relative to the file including it. `-` reads stdin and
+    // an http(s) url fetches the config; the includes of both are relative to
+    // the working directory.
+    fn load(path: &Path) -> Result<Config> {
+        let url = path
+            .to_str()
+            .filter(|path| path.starts_with("http://") || path.starts_with("https://"));
+        if let Some(url) = url {
+            let raw = Config::fetch(url)?;
+            return Config::parse(&raw, url, Path::new(""), &mut Vec::new());
+        }
+        if path == Path::new("-") {
+            let mut raw = String::new();
+            io::stdin()
+                .read_to_string(&mut raw)
+                .context("Unable to read the configuration from stdin.")?;
+            return Config::parse(&raw, "from stdin", Path::new(""), &mut Vec::new());
+        }
+        Config::load_included(path, &mut Vec::new())
+    }
+
+    // Goes through HTTP_PROXY and HTTPS_PROXY like an Http step.
+    fn fetch(url: &str) -> Result<String> {
+        let env = env::vars().collect();
+        let mut builder = Client::builder().no_proxy().user_agent(USER_AGENT);
+        for proxy in Http::proxies("", &env)? {
+            builder = builder.proxy(proxy);
+        }
+        let response = builder
+            .build()?
+            .get(url)
+            .send()
+            .with_context(|| format!("Unable to fetch {}.", url))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Fetching {} failed with {}.", url, status));
+        }
+        Ok(response.text()?)
+    }
+
+    // `including` holds the files being loaded, to catch cycles.
+    fn load_included(path: &Path, including: &mut Vec<PathBuf>) -> Result<Config> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("Unable to read {}.", path.display()))?;
+        if including.contains(&canonical) {
+            return Err(anyhow!("Cyclic include of {}.", path.display()));
+        }
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}.", path.display()))?;
+
+        including.push(canonical);
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let config = Config::parse(&raw, &path.display().to_string(), base, including)?;
+        including.pop();
+        Ok(config)
+    }
+
+    // `source` names where raw came from in errors. Sources ending in `.json`
+    // are read as JSON, everything else as YAML.
+    fn parse(raw: &str, source: &str, base: &Path, including: &mut Vec<PathBuf>) -> Result<Config> {
+        let invalid = || format!("Invalid configuration {}.", source);
+        let mut config: Config = if source.to_lowercase().ends_with(".json") {
+            serde_json::from_str(raw).with_context(invalid)?
+        } else {
+            serde_yaml::from_str(raw).with_context(invalid)?
+        };
+        let mut workflows = Vec::new();
+        for step in mem::take(&mut config.workflows) {
+            match &step.include {
+                Some(include) => {
+                    let included = base.join(include);
+                    workflows.extend(Config::load_included(&included, including)?.workflows);
+                }
+                None => workflows.push(step),
+            }
+        }
+        config.workflows = workflows;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 struct WorkflowConfig {
-    #[serde(rename = "type")]
+    // Lets later steps read this step's outputs as `{steps.name.field}`.
+    name: Option<String>,
+    // Skips the step, passing its input straight on, when false. See
+    // parser::condition.
+    #[serde(rename = "if")]
+    condition: Option<String>,
+    #[serde(rename = "type", default)]
     workflow_type: String,
+    #[serde(default)]
     parameters: HashMap<String, String>,
+    // Steps run at the same time in place of a type. Outputs of named steps are
+    // merged as `name.field`, others as they are.
+    #[serde(default)]
+    parallel: Vec<WorkflowConfig>,
+    // Stands for the steps of another config file. See Config::load.
+    include: Option<String>,
+    // Runs the step once per item, bound to `{item}`. Outputs of every run are
+    // joined line by line under the same names.
+    foreach: Option<Items>,
+    // Passes the step's input on with a step_error output instead of failing.
+    #[serde(default)]
+    continue_on_error: bool,
+    // Runs a failing step again from the same context, up to this many times.
+    #[serde(default)]
+    retries: u32,
+    retry_delay_ms: Option<u64>,
+    // Renames outputs, e.g. `text: first_response`. Others keep their names.
+    #[serde(default)]
+    outputs: HashMap<String, String>,
+}
+
+// A YAML list, or text filled like a parameter and split into lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Items {
+    List(Vec<String>),
+    Lines(String),
 }
 
 impl WorkflowConfig {
-    fn execute(&self, context: &mut Context, output: Outputs) -> Result<()> {
-        let input: HashMap<String, String> = output
-            .into_iter()
-            .map(|(k, v)| (k.to_string(), v))
+    // Collects what would stop the step from running, labelled like `Step 2.1`
+    // for the first branch of the second step.
+    fn validate(&self, label: &str, problems: &mut Vec<String>) {
+        if !self.parallel.is_empty() {
+            if !self.workflow_type.is_empty() {
+                problems.push(format!("{} has both a type and parallel steps.", label));
+            }
+            for (i, branch) in self.parallel.iter().enumerate() {
+                branch.validate(&format!("{}.{}", label, i + 1), problems);
+            }
+        } else if self.workflow_type.is_empty() {
+            problems.push(format!("{} has neither a type nor parallel steps.", label));
+        } else if let Some(workflow) = WORKFLOWS.get(&self.workflow_type.to_lowercase()[..]) {
+            for key in self.missing(workflow) {
+                problems.push(format!(
+                    "{} ({}) is missing the required parameter {}.",
+                    label, self.workflow_type, key
+                ));
+            }
+        } else {
+            problems.push(format!(
+                "{} uses workflow {}, which is not found.",
+                label, self.workflow_type
+            ));
+        }
+        let mut templates: Vec<_> = self
+            .parameters
+            .iter()
+            .map(|(key, raw)| (key.as_str(), raw))
             .collect();
-        let (workflow, payload) = self.make_workflow(&input, &context)?;
-        workflow.execute(context, payload)
+        templates.sort();
+        templates.extend(self.condition.iter().map(|raw| ("if", raw)));
+        for (key, raw) in templates {
+            if let Err(e) = check(raw) {
+                problems.push(format!("{} has an invalid {}: {}", label, key, e));
+            }
+        }
+    }
+
+    // The required parameters of the workflow the step leaves out.
+    fn missing(&self, workflow: &SupportedWorkflows) -> Vec<&'static str> {
+        workflow
+            .required_parameters()
+            .iter()
+            .copied()
+            .filter(|key| !self.parameters.contains_key(*key))
+            .collect()
+    }
+
+    // Whether a template of the step, or of its branches, reads
+    // `{steps.name.field}`.
+    fn reads_step(&self, name: &str) -> bool {
+        let reference = format!("steps.{}.", name);
+        let items = match &self.foreach {
+            Some(Items::List(items)) => items.iter().collect(),
+            Some(Items::Lines(raw)) => vec![raw],
+            None => Vec::new(),
+        };
+        self.parameters
+            .values()
+            .chain(&self.condition)
+            .chain(items)
+            .any(|raw| raw.contains(&reference))
+            || self.parallel.iter().any(|branch| branch.reads_step(name))
+    }
+
+    // The names of the step and its branches.
+    fn names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        names.extend(self.name.as_deref());
+        for branch in &self.parallel {
+            branch.names(names);
+        }
+    }
+
+    fn skipped(&self, context: &Context, input: &Outputs) -> Result<bool> {
+        match &self.condition {
+            Some(raw) => condition(raw, input, context)
+                .map(|run| !run)
+                .with_context(|| format!("Unable to evaluate if of the {} step.", self.kind())),
+            None => Ok(false),
+        }
+    }
+
+    fn kind(&self) -> &str {
+        if self.parallel.is_empty() {
+            &self.workflow_type
+        } else {
+            "parallel"
+        }
+    }
+
+    const STEP_ERROR: &'static str = "step_error";
+    const DEFAULT_RETRY_DELAY_MS: u64 = 1000;
+
+    fn attempt(&self, context: &mut Context, input: Outputs) -> Result<Vec<Outputs>> {
+        match self.retry(context, input.clone()) {
+            Ok(outputs) => Ok(outputs
+                .into_iter()
+                .map(|output| self.rename(output))
+                .collect()),
+            Err(e) if self.continue_on_error => {
+                warn!("The {} step failed, continuing: {:#}", self.kind(), e);
+                let mut output = input;
+                output.insert(WorkflowConfig::STEP_ERROR.to_string(), format!("{:#}", e));
+                Ok(vec![output])
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn rename(&self, output: Outputs) -> Outputs {
+        output
+            .into_iter()
+            .map(|(key, value)| match self.outputs.get(&key) {
+                Some(renamed) => (renamed.clone(), value),
+                None => (key, value),
+            })
+            .collect()
+    }
+
+    fn retry(&self, context: &mut Context, input: Outputs) -> Result<Vec<Outputs>> {
+        if self.retries == 0 {
+            return self.execute(context, input);
+        }
+        let snapshot = context.clone();
+        let delay = self
+            .retry_delay_ms
+            .unwrap_or(WorkflowConfig::DEFAULT_RETRY_DELAY_MS);
+        let mut attempt = 0;
+        loop {
+            match self.execute(context, input.clone()) {
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        "The {} step failed, retrying ({}/{}): {:#}",
+                        self.kind(),
+                        attempt,
+                        self.retries,
+                        e
+                    );
+                    *context = snapshot.clone();
+                    thread::sleep(Duration::from_millis(delay));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn execute(&self, context: &mut Context, input: Outputs) -> Result<Vec<Outputs>> {
+        if let Some(items) = &self.foreach {
+            return Ok(vec![self.execute_foreach(items, context, input)?]);
+        }
+        self.execute_once(context, input)
+    }
+
+    fn execute_once(&self, context: &mut Context, input: Outputs) -> Result<Vec<Outputs>> {
+        if !self.parallel.is_empty() {
+            return Ok(vec![self.execute_parallel(context, input)?]);
+        }
+        let (workflow, payload) = self.make_workflow(&input, context)?;
+        if context.dry_run {
+            return Ok(vec![self.plan(context, workflow, &payload)]);
+        }
+        info!("Running the {} step.", self.workflow_type);
+        for (key, value) in context.masked(&payload) {
+            info!("  {}: {}", key, value);
+        }
+        context.input = input;
+        let outputs = workflow.execute(context, payload)?;
+        for output in &outputs {
+            debug!(
+                "The {} step returned {:?}.",
+                self.workflow_type,
+                context.masked(output)
+            );
+        }
+        Ok(outputs)
+    }
+
+    // Prints the filled parameters and stands in `<type.output>` for every
+    // output, so later steps can be filled too.
+    fn plan(&self, context: &Context, workflow: &SupportedWorkflows, payload: &Inputs) -> Outputs {
+        println!("Would run {}:", self.workflow_type);
+        for (key, value) in context.masked(payload) {
+            println!("  {}: {}", key, value);
+        }
+        workflow
+            .outputs()
+            .iter()
+            .map(|output| {
+                let placeholder = format!("<{}.{}>", self.workflow_type, output);
+                (output.to_string(), placeholder)
+            })
+            .collect()
+    }
+
+    // An empty list runs the step zero times and yields an empty output.
+    fn execute_foreach(
+        &self,
+        items: &Items,
+        context: &mut Context,
+        input: Outputs,
+    ) -> Result<Outputs> {
+        let fill = |raw: &str| {
+            fulfill(raw, &input, context)
+                .with_context(|| format!("Unable to fill foreach of the {} step.", self.kind()))
+        };
+        let items = match items {
+            Items::List(items) => items.iter().map(|item| fill(item)).collect::<Result<_>>()?,
+            Items::Lines(raw) => fill(raw)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        };
+
+        let mut collected: HashMap<String, Vec<String>> = HashMap::new();
+        for item in items {
+            context.item = Some(item);
+            let outputs = self.execute_once(context, input.clone());
+            context.item = None;
+            for output in outputs? {
+                for (key, value) in output {
+                    collected.entry(key).or_default().push(value);
+                }
+            }
+        }
+        Ok(collected
+            .into_iter()
+            .map(|(key, values)| (key, values.join("\n")))
+            .collect())
+    }
+
+    // Each branch runs on its own thread with a copy of the context. Any failing
+    // branch fails the group once all have finished.
+    fn execute_parallel(&self, context: &mut Context, input: Outputs) -> Result<Outputs> {
+        let shared = &*context;
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .parallel
+                .iter()
+                .map(|branch| {
+                    let mut context = shared.clone();
+                    let input = input.clone();
+                    scope.spawn(move || -> Result<_> {
+                        if branch.skipped(&context, &input)? {
+                            return Ok((Vec::new(), context.steps));
+                        }
+                        let outputs = branch.attempt(&mut context, input)?;
+                        Ok((outputs, context.steps))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("A parallel step panicked.")))
+                })
+                .collect()
+        });
+
+        let mut merged = Outputs::new();
+        for (branch, result) in self.parallel.iter().zip(results) {
+            let (mut outputs, steps) = result?;
+            context.steps.extend(steps);
+            if outputs.len() > 1 {
+                return Err(anyhow!(
+                    "The parallel {} step returned {} outputs, but at most one is allowed.",
+                    branch.kind(),
+                    outputs.len()
+                ));
+            }
+            let output = match outputs.pop() {
+                Some(output) => output,
+                None => continue,
+            };
+            match &branch.name {
+                Some(name) => {
+                    context.steps.insert(name.clone(), output.clone());
+                    merged.extend(
+                        output
+                            .into_iter()
+                            .map(|(key, value)| (format!("{}.{}", name, key), value)),
+                    );
+                }
+                None => merged.extend(output),
+            }
+        }
+        Ok(merged)
     }
 
     fn make_workflow(
@@ -137,28 +720,583 @@ impl WorkflowConfig {
         let workflow = WORKFLOWS
             .get(&self.workflow_type.to_lowercase()[..])
             .context(anyhow!("Workflow {} is not found.", self.workflow_type))?;
+        if let Some(key) = self.missing(workflow).first() {
+            return Err(anyhow!(
+                "The {} step is missing the required parameter {}.",
+                self.workflow_type,
+                key
+            ));
+        }
         let mut payload: HashMap<&'static str, String> = HashMap::new();
         for key in workflow.parameters() {
             if let Some(value) = self.parameters.get(*key) {
-                payload.insert(key, fulfill(value, input, &context)?);
+                let filled = fulfill(value, input, context).with_context(|| {
+                    format!("Unable to fill {} of the {} step.", key, self.workflow_type)
+                })?;
+                debug!(
+                    "Filled {} {:?} as {:?}.",
+                    key,
+                    context.mask(key, value),
+                    context.mask(key, &filled)
+                );
+                payload.insert(key, filled);
             }
         }
         Ok((workflow, payload))
     }
 }
 
+// The workflow types for --list, each with its parameters and outputs.
+fn list() -> String {
+    let mut types: Vec<_> = WORKFLOWS.iter().collect();
+    types.sort_by_key(|(name, _)| *name);
+    let join = |names: &[&str]| match names {
+        [] => "none".to_string(),
+        names => names.join(", "),
+    };
+    types
+        .into_iter()
+        .map(|(name, workflow)| {
+            format!(
+                "{}\n  parameters: {}\n  outputs: {}\n",
+                name,
+                join(workflow.parameters()),
+                join(workflow.outputs())
+            )
+        })
+        .collect()
+}
+
+// Keeps only the step named by --only, or the steps from the one named by
+// --from on. The steps left out must not feed the ones kept.
+fn select(
+    mut steps: Vec<WorkflowConfig>,
+    only: Option<&str>,
+    from: Option<&str>,
+) -> Result<Vec<WorkflowConfig>> {
+    let name = match only.or(from) {
+        Some(name) => name,
+        None => return Ok(steps),
+    };
+    let index = steps
+        .iter()
+        .position(|step| step.name.as_deref() == Some(name))
+        .with_context(|| format!("No step is named {}.", name))?;
+    let mut kept = steps.split_off(index);
+    if only.is_some() {
+        kept.truncate(1);
+    }
+
+    let mut left_out = Vec::new();
+    for step in &steps {
+        step.names(&mut left_out);
+    }
+    for step in &kept {
+        if let Some(missing) = left_out.iter().find(|name| step.reads_step(name)) {
+            return Err(anyhow!(
+                "Step {} reads the outputs of step {}, which does not run.",
+                step.name.as_deref().unwrap_or_else(|| step.kind()),
+                missing
+            ));
+        }
+    }
+    Ok(kept)
+}
+
+// Checks every step before any runs, so a bad step cannot leave the ones
+// before it half done.
+fn validate(steps: &[WorkflowConfig]) -> Result<()> {
+    let mut problems = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        step.validate(&format!("Step {}", i + 1), &mut problems);
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid configuration:\n{}", problems.join("\n")))
+    }
+}
+
+// Runs the steps in order. A step that returns several outputs runs the rest of
+// the steps once for each of them.
+// `index` counts the steps already behind `steps`, for error messages.
+fn run(
+    context: &mut Context,
+    steps: &[WorkflowConfig],
+    index: usize,
+    input: Outputs,
+) -> Result<()> {
+    let (step, rest) = match steps.split_first() {
+        Some(split) => split,
+        None => {
+            context.results.push(input);
+            return Ok(());
+        }
+    };
+    let failed = || match &step.name {
+        Some(name) => format!("Step {} ({}) failed.", name, step.kind()),
+        None => format!("Step {} ({}) failed.", index + 1, step.kind()),
+    };
+    if step.skipped(context, &input).with_context(failed)? {
+        return run(context, rest, index + 1, input);
+    }
+    let depth = context.history.len();
+    for output in step.attempt(context, input).with_context(failed)? {
+        if let Some(name) = &step.name {
+            context.steps.insert(name.clone(), output.clone());
+        }
+        let label = step.name.as_deref().unwrap_or_else(|| step.kind());
+        context.history.truncate(depth);
+        context.history.push((label.to_string(), output.clone()));
+        run(context, rest, index + 1, output)?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    let config_path = env::args()
-        .nth(1)
-        .context("No configuration is provided.")?;
+    let args = Args::parse(env::args().skip(1))?;
+    // -v only makes this crate chattier; RUST_LOG still reaches dependencies.
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Warn)
+        .filter_module(
+            module_path!(),
+            match args.verbose {
+                0 => LevelFilter::Warn,
+                1 => LevelFilter::Info,
+                _ => LevelFilter::Debug,
+            },
+        )
+        .format_timestamp(None)
+        .parse_default_env()
+        .init();
 
-    let config = fs::read_to_string(config_path)?;
-    let config: Config = serde_yaml::from_str(&config)?;
+    if args.list {
+        print!("{}", list());
+        return Ok(());
+    }
+    let mut config = Config::load(Path::new(&args.config))?;
+    config.vars.extend(args.set);
+    let workflows = mem::take(&mut config.workflows);
+    validate(&workflows)?;
+    let workflows = select(workflows, args.only.as_deref(), args.from.as_deref())?;
+    if args.check {
+        println!("{} is valid.", args.config);
+        return Ok(());
+    }
 
     let mut context = Context::new(config);
-    if let Some(next) = context.next() {
-        next.execute(&mut context, HashMap::new())?;
+    context.dry_run = args.dry_run;
+    if let Some(path) = &args.env_file {
+        context.load_env_file(path)?;
+    }
+    context.fill_vars()?;
+    run(&mut context, &workflows, 0, HashMap::new())?;
+    if let Some(output) = args.output.or_else(|| context.config.output.clone()) {
+        context.write_results(&output)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+workflows:
+  - type: echo
+  - type: ech0
+  - parallel:
+      - type: Http
+      - type: upload
+  - parameters: {}
+  - type: echo
+    if: "{input.ok | bool}"
+    parameters:
+      text: "{input.text | uper}"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            validate(&config.workflows).unwrap_err().to_string(),
+            "Invalid configuration:\n\
+             Step 2 uses workflow ech0, which is not found.\n\
+             Step 3.1 (Http) is missing the required parameter url.\n\
+             Step 3.2 uses workflow upload, which is not found.\n\
+             Step 4 has neither a type nor parallel steps.\n\
+             Step 5 has an invalid text: Unknown filter uper.\n\
+             Step 5 has an invalid if: Unknown filter bool."
+        );
+
+        let context = test_util::context();
+        let http = &config.workflows[2].parallel[0];
+        assert_eq!(
+            http.make_workflow(&Outputs::new(), &context)
+                .err()
+                .unwrap()
+                .to_string(),
+            "The Http step is missing the required parameter url."
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let list = list();
+        assert!(list.starts_with("atom\n"));
+        assert!(list.contains("\nsleep\n  parameters: duration_ms\n  outputs: none\n"));
+        assert!(list
+            .contains("\nhash\n  parameters: path, algorithm, expected\n  outputs: hash, path\n"));
+    }
+
+    #[test]
+    fn test_select() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+workflows:
+  - name: fetch
+    type: http
+  - parallel:
+      - name: version
+        type: read
+  - name: build
+    type: command
+  - name: publish
+    type: gist
+    parameters:
+      text: "{steps.build.text}"
+  - name: notify
+    type: echo
+    parameters:
+      text: "{steps.version.text}"
+"#,
+        )
+        .unwrap();
+        let names = |steps: Vec<WorkflowConfig>| -> Vec<_> {
+            steps
+                .into_iter()
+                .map(|step| step.kind().to_string())
+                .collect()
+        };
+        let select = |only, from| select(config.workflows.clone(), only, from);
+
+        assert_eq!(select(None, None).unwrap().len(), 5);
+        assert_eq!(names(select(Some("build"), None).unwrap()), ["command"]);
+        assert_eq!(select(None, Some("fetch")).unwrap().len(), 5);
+        assert_eq!(
+            select(Some("publish"), None).unwrap_err().to_string(),
+            "Step publish reads the outputs of step build, which does not run."
+        );
+        assert_eq!(
+            select(None, Some("build")).unwrap_err().to_string(),
+            "Step notify reads the outputs of step version, which does not run."
+        );
+        assert_eq!(
+            select(Some("deploy"), None).unwrap_err().to_string(),
+            "No step is named deploy."
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = test_util::scratch("include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, steps: &str| {
+            fs::write(dir.join(name), format!("workflows:\n{}", steps)).unwrap();
+        };
+        write(
+            "main.yml",
+            "  - type: echo\n  - include: lib/notify.yml\n  - type: save\n",
+        );
+        write(
+            "lib/notify.yml",
+            "  - include: wechat.yml\n  - type: echo\n",
+        );
+        write("lib/wechat.yml", "  - type: wechat\n");
+
+        let config = Config::load(&dir.join("main.yml")).unwrap();
+        let types: Vec<_> = config
+            .workflows
+            .iter()
+            .map(|step| &step.workflow_type[..])
+            .collect();
+        assert_eq!(types, ["echo", "wechat", "echo", "save"]);
+
+        // A config from stdin includes relative to the working directory,
+        // stood in for here by dir.
+        let raw = "workflows:\n  - include: lib/wechat.yml\n";
+        let config = Config::parse(raw, "from stdin", &dir, &mut Vec::new()).unwrap();
+        assert_eq!(config.workflows[0].workflow_type, "wechat");
+
+        write("lib/wechat.yml", "  - include: ../main.yml\n");
+        let error = Config::load(&dir.join("main.yml")).unwrap_err();
+        assert!(error.to_string().starts_with("Cyclic include of "));
+    }
+
+    #[test]
+    fn test_json_config() {
+        let dir = test_util::scratch("json");
+        fs::write(dir.join("notify.yml"), "workflows:\n  - type: wechat\n").unwrap();
+        fs::write(
+            dir.join("main.json"),
+            r#"{"workflows": [{"type": "echo"}, {"include": "notify.yml"}]}"#,
+        )
+        .unwrap();
+        let config = Config::load(&dir.join("main.json")).unwrap();
+        assert_eq!(config.workflows[1].workflow_type, "wechat");
+
+        fs::write(dir.join("broken.json"), r#"{"workflows": [}"#).unwrap();
+        let error = Config::load(&dir.join("broken.json")).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            format!(
+                "Invalid configuration {}.: expected value at line 1 column 16",
+                dir.join("broken.json").display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_load_url() {
+        let serve = |status: &str, body: &str| {
+            let (address, _) = test_util::serve_all(vec![test_util::response(status, body)]);
+            format!("{}/workflows.yml", address)
+        };
+
+        let url = serve("200 OK", "workflows:\n  - type: wechat\n");
+        let config = Config::load(Path::new(&url)).unwrap();
+        assert_eq!(config.workflows[0].workflow_type, "wechat");
+
+        let url = serve("404 Not Found", "");
+        assert_eq!(
+            Config::load(Path::new(&url)).unwrap_err().to_string(),
+            format!("Fetching {} failed with 404 Not Found.", url)
+        );
+    }
+
+    #[test]
+    fn test_write_results() {
+        let path = test_util::scratch("results").join("results.json");
+        let mut context = test_util::context();
+        let steps: Vec<WorkflowConfig> =
+            serde_yaml::from_str("[{type: echo, parameters: {text: hi}}]").unwrap();
+        context.dry_run = true;
+        let mut input = Outputs::new();
+        input.insert("token".to_string(), "ghp_123".to_string());
+        run(&mut context, &steps, 0, input).unwrap();
+        context.write_results(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[\n  {}\n]\n");
+
+        context.results = vec![vec![("token".to_string(), "ghp_123".to_string())]
+            .into_iter()
+            .collect()];
+        context.write_results(path.to_str().unwrap()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!([{"token": "***"}]));
+    }
+
+    #[test]
+    fn test_step_error_context() {
+        let steps: Vec<WorkflowConfig> = serde_yaml::from_str(
+            r#"
+- type: echo
+  parameters: {text: hi}
+- type: decompress
+  parameters: {path: /missing/archive.zip}
+- name: unpack
+  type: decompress
+  parameters: {path: /missing/archive.zip}
+"#,
+        )
+        .unwrap();
+        let mut context = test_util::context();
+        let error = run(&mut context, &steps, 0, Outputs::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Step 2 (decompress) failed.");
+        let error = run(&mut context, &steps[2..], 2, Outputs::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Step unpack (decompress) failed.");
+    }
+
+    #[test]
+    fn test_rename_outputs() {
+        let path = test_util::scratch("rename").join("response.txt");
+        fs::write(&path, "first").unwrap();
+        let steps: Vec<WorkflowConfig> = serde_yaml::from_str(&format!(
+            "[{{type: read, parameters: {{path: {}}}, outputs: {{text: first_response}}}}]",
+            path.display()
+        ))
+        .unwrap();
+        let mut context = test_util::context();
+        run(&mut context, &steps, 0, Outputs::new()).unwrap();
+        let result = &context.results[0];
+        assert_eq!(result["first_response"], "first");
+        assert!(!result.contains_key("text"));
+        assert_eq!(result["content"], "first");
+    }
+
+    #[test]
+    fn test_history() {
+        let path = test_util::scratch("history").join("response.txt");
+        fs::write(&path, "first").unwrap();
+        let steps: Vec<WorkflowConfig> = serde_yaml::from_str(&format!(
+            "- {{name: first, type: read, parameters: {{path: {}}}}}\n\
+             - {{type: save, parameters: {{path: {}, text: second}}}}\n\
+             - {{type: echo, parameters: {{text: \"{{history.2.text}}\"}}}}",
+            path.display(),
+            path.display()
+        ))
+        .unwrap();
+        let mut context = test_util::context();
+        run(&mut context, &steps, 0, Outputs::new()).unwrap();
+        let labels: Vec<_> = context.history.iter().map(|(step, _)| &step[..]).collect();
+        assert_eq!(labels, ["first", "save", "echo"]);
+        assert_eq!(context.history[0].1["text"], "first");
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn steps(raw: &str) -> Vec<WorkflowConfig> {
+        serde_yaml::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn test_parallel() {
+        let mut context = test_util::context();
+        let parallel = steps(
+            r#"
+- parallel:
+    - name: first
+      type: regex
+      parameters: {input: one, pattern: o.e}
+    - type: regex
+      parameters: {input: two, pattern: t.o}
+"#,
+        );
+        run(&mut context, &parallel, 0, Outputs::new()).unwrap();
+        let result = &context.results[0];
+        assert_eq!(result["first.match"], "one");
+        assert_eq!(result["match"], "two");
+        assert!(!result.contains_key("first"));
+        assert_eq!(context.steps["first"]["match"], "one");
+
+        let failing = steps(
+            r#"
+- parallel:
+    - type: regex
+      parameters: {input: one, pattern: o.e}
+    - type: regex
+      parameters: {input: two, pattern: three}
+"#,
+        );
+        let error = run(&mut context, &failing, 0, Outputs::new()).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Step 1 (parallel) failed.: Pattern three does not match the input."
+        );
+    }
+
+    #[test]
+    fn test_foreach() {
+        let mut context = test_util::context();
+        let foreach = steps(
+            r#"
+- type: regex
+  foreach: [a1, b2, c3]
+  parameters: {input: "{item}", pattern: '\d'}
+"#,
+        );
+        run(&mut context, &foreach, 0, Outputs::new()).unwrap();
+        assert_eq!(context.results[0]["match"], "1\n2\n3");
+        assert_eq!(context.item, None);
+
+        let mut context = test_util::context();
+        let empty = steps(
+            r#"
+- type: regex
+  foreach: "{input.items}"
+  parameters: {input: "{item}", pattern: '\d'}
+- type: echo
+"#,
+        );
+        let mut input = Outputs::new();
+        input.insert("items".to_string(), "\n".to_string());
+        run(&mut context, &empty, 0, input).unwrap();
+        assert_eq!(context.results, vec![Outputs::new()]);
+    }
+
+    #[test]
+    fn test_continue_on_error() {
+        let mut context = test_util::context();
+        let failing = steps(
+            r#"
+- type: regex
+  continue_on_error: true
+  parameters: {input: one, pattern: two}
+"#,
+        );
+        let mut input = Outputs::new();
+        input.insert("text".to_string(), "kept".to_string());
+        run(&mut context, &failing, 0, input).unwrap();
+        let result = &context.results[0];
+        assert_eq!(result["text"], "kept");
+        assert_eq!(
+            result[WorkflowConfig::STEP_ERROR],
+            "Pattern two does not match the input."
+        );
+    }
+
+    // The named branch succeeds on both attempts and reads its own earlier
+    // output, which only the snapshot taken before the step keeps from
+    // piling up.
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_restores_context() {
+        let marker = test_util::scratch("retry").join("marker");
+        let retried = steps(&format!(
+            r#"
+- retries: 2
+  retry_delay_ms: 1
+  parallel:
+    - name: count
+      type: regex
+      parameters: {{input: "{{steps.count.match}}x", pattern: .*}}
+    - type: command
+      parameters:
+        program: "test -e {marker} && exit 0; touch {marker}; exit 1"
+        shell: "true"
+        check: "true"
+"#,
+            marker = marker.display()
+        ));
+        let mut context = test_util::context_from("strict_templates: false\nworkflows: []");
+        run(&mut context, &retried, 0, Outputs::new()).unwrap();
+        assert!(marker.exists());
+        assert_eq!(context.results[0]["count.match"], "x");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_escaped_braces() {
+        let command = steps(
+            r#"
+- type: command
+  parameters:
+    program: printf
+    args: '%s \{print}'
+    capture_output: "true"
+"#,
+        );
+        let mut context = test_util::context();
+        run(&mut context, &command, 0, Outputs::new()).unwrap();
+        assert_eq!(context.results[0]["stdout"], "{print}");
+
+        let unescaped = steps("[{type: command, parameters: {program: printf, args: '{print}'}}]");
+        let error = run(&mut context, &unescaped, 0, Outputs::new()).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Step 1 (command) failed.: Unable to fill args of the command step.: \
+             Unterminated or unknown expression {print}."
+        );
+    }
+}
@@ -1,92 +1,317 @@
+use crate::util::{lookup, render};
 use crate::Context;
 use anyhow::{anyhow, Context as _, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use form_urlencoded::byte_serialize;
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
-    character::complete::{char, multispace0},
-    combinator::map,
-    multi::fold_many1,
-    sequence::{delimited, preceded, separated_pair},
+    character::complete::{alphanumeric1, char, multispace0, satisfy},
+    combinator::{map, not, opt, peek, recognize},
+    multi::{fold_many1, many0},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
 use std::collections::HashMap;
 
+// Replaces `{input.field}` with the previous step's outputs, `{env.NAME}` with
+// the environment, `{vars.name}` with the config's vars, `{steps.name.field}`
+// with the outputs of a named step, `{history.N.field}` with the outputs of the
+// step N back, `{history.1.field}` being the previous one, and `{item}` with
+// the current foreach item; each fails when missing. Filters follow the field, each
+// introduced by `|` and optionally taking an argument after `:`, e.g.
+// `{input.name | default: anonymous}`. Whitespace around `|` and `:` is ignored,
+// and an argument runs up to the next `|` or `}` with trailing whitespace
+// trimmed. Write `\{` for a brace that must stay literal even when an
+// expression follows it, e.g. `\{input.name}`.
+//
+// Every missing variable is reported at once, unless the config turns
+// strict_templates off, in which case they become empty strings. A brace
+// followed by a letter must open a valid expression, so a misspelled one
+// fails rather than passing through; without strict_templates it is kept as
+// is.
 pub fn fulfill(raw: &str, input: &HashMap<String, String>, context: &Context) -> Result<String> {
+    fill(raw, input, context, context.config.strict_templates)
+}
+
+// Finds what would stop a template from filling whatever its variables hold,
+// without filling it.
+pub fn check(raw: &str) -> Result<()> {
+    if raw.is_empty() {
+        return Ok(());
+    }
+    let (_, texts) = parse(raw).map_err(|_| anyhow!("Unable to parse expression {}.", raw))?;
+    for text in texts {
+        if let Text::Expression(expression) = text {
+            if let Some(filter) = expression
+                .filters
+                .iter()
+                .find(|filter| !FILTERS.contains(&filter.name))
+            {
+                return Err(anyhow!("Unknown filter {}.", filter.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decides a step's `if`. Either `left == right`, `left != right` or a single
+// value, which is true unless it is empty, `false` or `0`. Both sides are
+// trimmed, and missing variables count as empty rather than failing.
+pub fn condition(raw: &str, input: &HashMap<String, String>, context: &Context) -> Result<bool> {
+    let value =
+        |raw: &str| -> Result<String> { Ok(fill(raw, input, context, false)?.trim().to_string()) };
+    if let Some((left, right)) = raw.split_once("==") {
+        return Ok(value(left)? == value(right)?);
+    }
+    if let Some((left, right)) = raw.split_once("!=") {
+        return Ok(value(left)? != value(right)?);
+    }
+    let value = value(raw)?;
+    Ok(!(value.is_empty() || value.eq_ignore_ascii_case("false") || value == "0"))
+}
+
+fn fill(
+    raw: &str,
+    input: &HashMap<String, String>,
+    context: &Context,
+    strict: bool,
+) -> Result<String> {
     let (_, texts) = parse(raw).map_err(|_| anyhow!("Unable to parse expression {}.", raw))?;
     let mut result = String::new();
+    let mut missing = Vec::new();
     for text in texts {
-        result.push_str(match text {
-            Text::Literal(s) => s,
-            Text::Expression(Expression { namespace, field }) if namespace == "env" => context
-                .env
-                .get(field)
-                .with_context(|| format!("Missing {}.", field))?,
-            Text::Expression(Expression {
-                namespace: _,
-                field,
-            }) => input
-                .get(field)
-                .with_context(|| format!("Missing {}.", field))?,
-        });
+        match text {
+            Text::Literal(s) => result.push_str(s),
+            Text::Unknown(s) if strict => {
+                return Err(anyhow!("Unterminated or unknown expression {}.", s));
+            }
+            Text::Unknown(s) => result.push_str(s),
+            Text::Expression(expression) => match evaluate(&expression, input, context)? {
+                Some(value) => result.push_str(&value),
+                None => missing.push(match expression.field {
+                    "" => expression.namespace,
+                    field => field,
+                }),
+            },
+        }
     }
 
+    if strict && !missing.is_empty() {
+        return Err(anyhow!("Missing {}.", missing.join(", ")));
+    }
     Ok(result)
 }
 
+// Resolves an expression, or `None` when its variable is missing.
+fn evaluate(
+    expression: &Expression,
+    input: &HashMap<String, String>,
+    context: &Context,
+) -> Result<Option<String>> {
+    let field = expression.field;
+    let mut value = match expression.namespace {
+        "env" => context.env.get(field),
+        "steps" => field
+            .split_once('.')
+            .and_then(|(step, field)| context.steps.get(step)?.get(field)),
+        "history" => field.split_once('.').and_then(|(back, field)| {
+            let index = context.history.len().checked_sub(back.parse().ok()?)?;
+            context.history.get(index)?.1.get(field)
+        }),
+        "item" => context.item.as_ref(),
+        "vars" => context.vars.get(field),
+        _ => input.get(field),
+    }
+    .cloned();
+    for filter in &expression.filters {
+        value = Some(match (filter.name, value) {
+            // Only default may see a missing value.
+            ("default", Some(value)) if !value.is_empty() => value,
+            ("default", _) => filter.argument.unwrap_or("").to_string(),
+            (_, None) => return Ok(None),
+            (_, Some(value)) => apply(filter, field, value)?,
+        });
+    }
+    Ok(value)
+}
+
+const FILTERS: [&str; 9] = [
+    "default",
+    "json",
+    "upper",
+    "lower",
+    "trim",
+    "base64",
+    "base64decode",
+    "urlencode",
+    "urldecode",
+];
+
+fn apply(filter: &Filter, field: &str, value: String) -> Result<String> {
+    let argument = filter.argument.unwrap_or("");
+    match filter.name {
+        // Reads a dotted path out of a JSON value, e.g. `json: data.items.0.id`.
+        "json" => {
+            let json: Value = serde_json::from_str(&value)
+                .with_context(|| format!("{} is not valid JSON.", field))?;
+            Ok(render(lookup(&json, argument)?))
+        }
+        "upper" => Ok(value.to_uppercase()),
+        "lower" => Ok(value.to_lowercase()),
+        "trim" => Ok(value.trim().to_string()),
+        "base64" => Ok(STANDARD.encode(value)),
+        "base64decode" => {
+            let bytes = STANDARD
+                .decode(value.trim())
+                .with_context(|| format!("Unable to decode {} as base64.", field))?;
+            String::from_utf8(bytes).with_context(|| format!("Decoded {} is not UTF-8.", field))
+        }
+        // Follows application/x-www-form-urlencoded, so spaces become `+`.
+        "urlencode" => Ok(byte_serialize(value.as_bytes()).collect()),
+        "urldecode" => Ok(percent_decode_str(&value.replace('+', " "))
+            .decode_utf8()
+            .with_context(|| format!("Decoded {} is not UTF-8.", field))?
+            .into_owned()),
+        name => Err(anyhow!("Unknown filter {}.", name)),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Text<'a> {
     Literal(&'a str),
     Expression(Expression<'a>),
+    // Filled as is unless templates are strict.
+    Unknown(&'a str),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct Expression<'a> {
     namespace: &'a str,
     field: &'a str,
+    filters: Vec<Filter<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter<'a> {
+    name: &'a str,
+    argument: Option<&'a str>,
+}
+
+fn literal(input: &str) -> IResult<&str, Text<'_>> {
+    map(is_not("{\\"), |s: &str| Text::Literal(s))(input)
+}
+
+fn escaped(input: &str) -> IResult<&str, Text<'_>> {
+    map(tag("\\{"), |_| Text::Literal("{"))(input)
 }
 
-fn literal(input: &str) -> IResult<&str, Text> {
-    map(is_not("{"), |s: &str| Text::Literal(s))(input)
+// Any other backslash is kept as is, e.g. in Windows paths.
+fn backslash(input: &str) -> IResult<&str, Text<'_>> {
+    map(tag("\\"), Text::Literal)(input)
 }
 
 fn namespace(input: &str) -> IResult<&str, &str> {
-    alt((tag("input"), tag("env")))(input)
+    alt((
+        tag("input"),
+        tag("env"),
+        tag("steps"),
+        tag("history"),
+        tag("vars"),
+    ))(input)
 }
 
 fn field(input: &str) -> IResult<&str, &str> {
-    is_not("\t }")(input)
+    is_not("\t |}")(input)
+}
+
+fn argument(input: &str) -> IResult<&str, &str> {
+    map(
+        preceded(
+            delimited(multispace0, char(':'), multispace0),
+            opt(is_not("|}")),
+        ),
+        |argument: Option<&str>| argument.unwrap_or("").trim_end(),
+    )(input)
+}
+
+fn filter(input: &str) -> IResult<&str, Filter<'_>> {
+    map(
+        preceded(
+            delimited(multispace0, char('|'), multispace0),
+            pair(alphanumeric1, opt(argument)),
+        ),
+        |(name, argument)| Filter { name, argument },
+    )(input)
+}
+
+// `{item}`, bound by foreach, is the one variable without a field.
+fn variable(input: &str) -> IResult<&str, (&str, &str)> {
+    alt((
+        separated_pair(namespace, tag("."), field),
+        map(tag("item"), |item| (item, "")),
+    ))(input)
 }
 
-fn expression(input: &str) -> IResult<&str, Expression> {
-    map(separated_pair(namespace, tag("."), field), |(ns, f)| {
+fn expression(input: &str) -> IResult<&str, Expression<'_>> {
+    map(pair(variable, many0(filter)), |((ns, f), filters)| {
         Expression {
             namespace: ns,
             field: f,
+            filters,
         }
     })(input)
 }
 
-fn enclosed(input: &str) -> IResult<&str, Text> {
+fn enclosed(input: &str) -> IResult<&str, Text<'_>> {
     map(
         delimited(
             char('{'),
             preceded(multispace0, expression),
             preceded(multispace0, char('}')),
         ),
-        |expr| Text::Expression(expr),
+        Text::Expression,
     )(input)
 }
 
-fn parse(input: &str) -> IResult<&str, Vec<Text>> {
-    fold_many1(alt((literal, enclosed)), Vec::new(), |mut acc, t| {
-        acc.push(t);
-        acc
-    })(input)
+// A brace that cannot open an expression, e.g. in a JSON object or a regex
+// repetition: one followed by anything but a letter.
+fn brace(input: &str) -> IResult<&str, Text<'_>> {
+    map(
+        terminated(
+            tag("{"),
+            not(peek(satisfy(|c: char| c.is_ascii_alphabetic()))),
+        ),
+        Text::Literal,
+    )(input)
+}
+
+// A brace opening something that is not a valid expression, up to its closing
+// brace, e.g. a misspelled namespace.
+fn unknown(input: &str) -> IResult<&str, Text<'_>> {
+    map(
+        recognize(tuple((char('{'), opt(is_not("}")), opt(char('}'))))),
+        Text::Unknown,
+    )(input)
+}
+
+fn parse(input: &str) -> IResult<&str, Vec<Text<'_>>> {
+    fold_many1(
+        alt((literal, escaped, enclosed, brace, unknown, backslash)),
+        Vec::new(),
+        |mut acc, t| {
+            acc.push(t);
+            acc
+        },
+    )(input)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util;
 
     #[test]
     fn test_enclosed_expression() {
@@ -96,7 +321,8 @@ mod tests {
                 "",
                 Text::Expression(Expression {
                     namespace: "env",
-                    field: "gist_secret"
+                    field: "gist_secret",
+                    filters: vec![],
                 })
             ))
         );
@@ -106,7 +332,8 @@ mod tests {
                 "",
                 Text::Expression(Expression {
                     namespace: "input",
-                    field: "status_code"
+                    field: "status_code",
+                    filters: vec![],
                 })
             ))
         );
@@ -122,7 +349,8 @@ mod tests {
                     Text::Literal("hello "),
                     Text::Expression(Expression {
                         namespace: "env",
-                        field: "ttt"
+                        field: "ttt",
+                        filters: vec![],
                     })
                 ]
             ))
@@ -136,16 +364,339 @@ mod tests {
                     Text::Literal("这是 Server 结果 "),
                     Text::Expression(Expression {
                         namespace: "input",
-                        field: "status_code"
+                        field: "status_code",
+                        filters: vec![],
                     }),
                     Text::Literal(", 今天天气是 "),
                     Text::Expression(Expression {
                         namespace: "input",
-                        field: "text"
+                        field: "text",
+                        filters: vec![],
                     }),
                     Text::Literal("。"),
                 ]
             ))
         );
     }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            parse(r#"{"id": "{input.id}"}"#),
+            Ok((
+                "",
+                vec![
+                    Text::Literal("{"),
+                    Text::Literal(r#""id": ""#),
+                    Text::Expression(Expression {
+                        namespace: "input",
+                        field: "id",
+                        filters: vec![],
+                    }),
+                    Text::Literal(r#""}"#),
+                ]
+            ))
+        );
+    }
+
+    fn context() -> Context {
+        context_from("workflows: []")
+    }
+
+    // With a HOME of its own, whatever the machine running the tests has.
+    fn context_from(config: &str) -> Context {
+        let mut context = test_util::context_from(config);
+        context.env = vec![("HOME".to_string(), "/root".to_string())]
+            .into_iter()
+            .collect();
+        context
+    }
+
+    fn template(raw: &str, input: &[(&str, &str)]) -> Result<String> {
+        let input = input
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        fulfill(raw, &input, &context())
+    }
+
+    #[test]
+    fn test_env() {
+        let input = [("HOME", "/home/input")];
+        assert_eq!(
+            template("{env.HOME}/.cache {input.HOME}", &input).unwrap(),
+            "/root/.cache /home/input"
+        );
+        assert_eq!(
+            template("{env.MISSING}", &input).unwrap_err().to_string(),
+            "Missing MISSING."
+        );
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(
+            enclosed("{ input.name | default : anonymous user }"),
+            Ok((
+                "",
+                Text::Expression(Expression {
+                    namespace: "input",
+                    field: "name",
+                    filters: vec![Filter {
+                        name: "default",
+                        argument: Some("anonymous user")
+                    }],
+                })
+            ))
+        );
+        let raw = "Hi {input.name | default: there}!";
+        assert_eq!(template(raw, &[("name", "Ann")]).unwrap(), "Hi Ann!");
+        assert_eq!(template(raw, &[("name", "")]).unwrap(), "Hi there!");
+        assert_eq!(template(raw, &[]).unwrap(), "Hi there!");
+        assert_eq!(template("{input.name|default:}", &[]).unwrap(), "");
+        assert_eq!(template("{env.SHELL | default: sh}", &[]).unwrap(), "sh");
+        assert_eq!(
+            template("{input.name}", &[]).unwrap_err().to_string(),
+            "Missing name."
+        );
+    }
+
+    #[test]
+    fn test_json_filter() {
+        let input = [("text", r#"{"data": {"id": 42, "tags": ["a", "b"]}}"#)];
+        assert_eq!(
+            template("{input.text | json: data.id}", &input).unwrap(),
+            "42"
+        );
+        assert_eq!(
+            template("{input.text | json: data.tags.1}", &input).unwrap(),
+            "b"
+        );
+        assert_eq!(
+            template("{input.text | json: data.name}", &input)
+                .unwrap_err()
+                .to_string(),
+            "Missing name in path data.name."
+        );
+        assert_eq!(
+            template("{input.text | json: id}", &[("text", "oops")])
+                .unwrap_err()
+                .to_string(),
+            "text is not valid JSON."
+        );
+    }
+
+    #[test]
+    fn test_strict_templates() {
+        let raw = "{input.a}-{env.B}-{input.c}";
+        let input = [("c", "c")];
+        assert_eq!(
+            template(raw, &input).unwrap_err().to_string(),
+            "Missing a, B."
+        );
+
+        let input = input
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let lenient = context_from("strict_templates: false\nworkflows: []");
+        assert_eq!(fulfill(raw, &input, &lenient).unwrap(), "--c");
+
+        let input = [("url", "https://example.com")];
+        assert_eq!(
+            template("GET {inptu.url}", &input).unwrap_err().to_string(),
+            "Unterminated or unknown expression {inptu.url}."
+        );
+        assert_eq!(
+            template("{input.url} {steps.a.b", &input)
+                .unwrap_err()
+                .to_string(),
+            "Unterminated or unknown expression {steps.a.b."
+        );
+        let input = HashMap::new();
+        assert_eq!(
+            fulfill("GET {inptu.url}", &input, &lenient).unwrap(),
+            "GET {inptu.url}"
+        );
+        assert_eq!(
+            template(r#"{"id": 1} { } {} \d{1,3}"#, &[]).unwrap(),
+            r#"{"id": 1} { } {} \d{1,3}"#
+        );
+    }
+
+    #[test]
+    fn test_escaped_brace() {
+        let input = [("name", "Ann")];
+        assert_eq!(
+            template(r"\{input.name}{input.name}", &input).unwrap(),
+            "{input.name}Ann"
+        );
+        assert_eq!(
+            template(r"{input.name}\{input.name}\{{input.name}}", &input).unwrap(),
+            "Ann{input.name}{Ann}"
+        );
+        assert_eq!(
+            template(r"C:\Users\{input.name}", &input).unwrap(),
+            r"C:\Users{input.name}"
+        );
+        assert_eq!(template(r"a\b\", &input).unwrap(), r"a\b\");
+    }
+
+    #[test]
+    fn test_string_filters() {
+        let input = [("name", "  Ann Lee \n")];
+        assert_eq!(template("{input.name | trim}", &input).unwrap(), "Ann Lee");
+        assert_eq!(
+            template("{input.name | trim | upper}!", &input).unwrap(),
+            "ANN LEE!"
+        );
+        assert_eq!(
+            template("{input.name|lower|trim}", &input).unwrap(),
+            "ann lee"
+        );
+        assert_eq!(
+            template("{input.name | shout}", &input)
+                .unwrap_err()
+                .to_string(),
+            "Unknown filter shout."
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check("").is_ok());
+        assert!(check("{input.name | default: x | trim} \\{ {").is_ok());
+        // Caught even where the variable would be missing when filled.
+        assert_eq!(
+            check("{steps.build.text | uper}").unwrap_err().to_string(),
+            "Unknown filter uper."
+        );
+    }
+
+    #[test]
+    fn test_base64_filters() {
+        let input = [("user", "ann"), ("encoded", "YW5uOmh1bnRlcjI=")];
+        assert_eq!(
+            template("Basic {input.user | base64}", &input).unwrap(),
+            "Basic YW5u"
+        );
+        assert_eq!(
+            template("{input.encoded | base64decode}", &input).unwrap(),
+            "ann:hunter2"
+        );
+        assert_eq!(
+            template("{input.user | base64decode}", &input)
+                .unwrap_err()
+                .to_string(),
+            "Unable to decode user as base64."
+        );
+    }
+
+    #[test]
+    fn test_url_filters() {
+        let input = [("query", "tea & café"), ("encoded", "tea+%26+caf%C3%A9")];
+        assert_eq!(
+            template("q={input.query | urlencode}", &input).unwrap(),
+            "q=tea+%26+caf%C3%A9"
+        );
+        assert_eq!(
+            template("{input.encoded | urldecode}", &input).unwrap(),
+            "tea & café"
+        );
+        assert_eq!(
+            template("{input.query | urlencode | urldecode}", &input).unwrap(),
+            "tea & café"
+        );
+    }
+
+    #[test]
+    fn test_steps() {
+        let input = HashMap::new();
+        let mut context = context();
+        context.steps.insert(
+            "download".to_string(),
+            vec![("output_file".to_string(), "a.tar.gz".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            fulfill("{steps.download.output_file}", &input, &context).unwrap(),
+            "a.tar.gz"
+        );
+        assert_eq!(
+            fulfill("{steps.upload.url}", &input, &context)
+                .unwrap_err()
+                .to_string(),
+            "Missing upload.url."
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let input = HashMap::new();
+        let mut context = context();
+        for (step, text) in [("fetch", "feed"), ("rss", "entry")] {
+            let outputs = vec![("text".to_string(), text.to_string())];
+            context
+                .history
+                .push((step.to_string(), outputs.into_iter().collect()));
+        }
+        assert_eq!(
+            fulfill("{history.2.text} > {history.1.text}", &input, &context).unwrap(),
+            "feed > entry"
+        );
+        assert_eq!(
+            fulfill(
+                "{history.3.text}{history.0.text}{history.x.text}",
+                &input,
+                &context
+            )
+            .unwrap_err()
+            .to_string(),
+            "Missing 3.text, 0.text, x.text."
+        );
+    }
+
+    #[test]
+    fn test_condition() {
+        let input = [("status_code", "200"), ("enabled", "False")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let context = context();
+        let check = |raw| condition(raw, &input, &context).unwrap();
+        assert!(check("{input.status_code} == 200"));
+        assert!(!check("{input.status_code}==404"));
+        assert!(check("{input.status_code} != 404"));
+        assert!(check("{input.status_code}"));
+        assert!(!check("{input.enabled}"));
+        assert!(!check("{input.missing}"));
+        assert!(check("{input.missing} == "));
+        assert!(!check("0"));
+    }
+
+    #[test]
+    fn test_item() {
+        let input = HashMap::new();
+        let mut context = context();
+        assert_eq!(
+            fulfill("{item}", &input, &context).unwrap_err().to_string(),
+            "Missing item."
+        );
+        context.item = Some("a.zip".to_string());
+        assert_eq!(
+            fulfill("unzip {item | upper}", &input, &context).unwrap(),
+            "unzip A.ZIP"
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let mut context = context_from("vars:\n  base: \"{env.HOME}/cache\"\nworkflows: []");
+        context.fill_vars().unwrap();
+        assert_eq!(
+            fulfill("{vars.base}/a.zip", &HashMap::new(), &context).unwrap(),
+            "/root/cache/a.zip"
+        );
+    }
 }
@@ -1,37 +1,81 @@
 use crate::{Context, Input, Inputs, Outputs, Workflow};
-use anyhow::Result;
-use std::{fs::File, io::Read as _};
+use anyhow::{Context as _, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::fs;
+use strum::EnumString;
 
 pub struct Read {}
 
+#[derive(Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum Encoding {
+    Text,
+    // For binary files.
+    Base64,
+}
+
 impl Read {
     // Input
     const PATH: &'static str = "path";
-    const PARAMS: [&'static str; 1] = [Read::PATH];
+    // `text` (default) or `base64`.
+    const ENCODING: &'static str = "encoding";
+    const PARAMS: [&'static str; 2] = [Read::PATH, Read::ENCODING];
+    const REQUIRED: [&'static str; 1] = [Read::PATH];
 
+    // Output
+    // Both hold the file's contents; content is the name FileRead steps use.
     const TEXT: &'static str = "text";
-    const OUTPUT: [&'static str; 1] = [Read::TEXT];
+    const CONTENT: &'static str = "content";
+    const OUTPUT: [&'static str; 2] = [Read::TEXT, Read::CONTENT];
 }
 
 impl Workflow for Read {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let path = input.parameter(Read::PATH);
+        let encoding = input.parse(Read::ENCODING)?.unwrap_or(Encoding::Text);
 
-        let mut text = String::new();
-        File::open(path)?.read_to_string(&mut text)?;
+        let unreadable = || format!("Unable to read {}.", path);
+        let text = match encoding {
+            Encoding::Text => fs::read_to_string(path).with_context(unreadable)?,
+            Encoding::Base64 => STANDARD.encode(fs::read(path).with_context(unreadable)?),
+        };
 
         let mut output = Outputs::new();
-        output.insert(Read::TEXT, text);
-        if let Some(next) = context.next() {
-            next.execute(context, output)?;
-        }
-        Ok(())
+        output.insert(Read::CONTENT.to_string(), text.clone());
+        output.insert(Read::TEXT.to_string(), text);
+        Ok(vec![output])
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Read::PARAMS;
+        &Read::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Read::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Read::OUTPUT;
+        &Read::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+
+    #[test]
+    fn test_encoding() {
+        let path = scratch("read").join("input.bin");
+        fs::write(&path, [0xff, 0x00, b'a']).unwrap();
+        let path = path.to_str().unwrap();
+
+        let outputs = run(Read {}, &[(Read::PATH, path), (Read::ENCODING, "base64")]).unwrap();
+        assert_eq!(outputs[0][Read::CONTENT], "/wBh");
+        assert!(run(Read {}, &[(Read::PATH, path)]).is_err());
+        assert_eq!(
+            run(Read {}, &[(Read::PATH, "/missing/file")])
+                .unwrap_err()
+                .to_string(),
+            "Unable to read /missing/file."
+        );
     }
 }
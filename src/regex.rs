@@ -0,0 +1,172 @@
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{anyhow, Context as _, Result};
+use regex::{Captures, Regex as Pattern};
+use std::collections::HashMap;
+
+pub struct Regex {}
+
+impl Regex {
+    // Input
+    const INPUT: &'static str = "input";
+    // Filled like any template first, so a brace before a letter must be
+    // written `\{`, e.g. `\p\{L}` for `\p{L}`.
+    const PATTERN: &'static str = "pattern";
+    // The index or name of the capture group to take. Defaults to the first
+    // group, or the whole match when the pattern has none.
+    const GROUP: &'static str = "group";
+    // Takes every match, one per line, instead of the first.
+    const ALL: &'static str = "all";
+    // Outputs an empty match instead of failing when nothing matches.
+    const MISSING_OK: &'static str = "missing_ok";
+    const PARAMS: [&'static str; 5] = [
+        Regex::INPUT,
+        Regex::PATTERN,
+        Regex::GROUP,
+        Regex::ALL,
+        Regex::MISSING_OK,
+    ];
+    const REQUIRED: [&'static str; 2] = [Regex::INPUT, Regex::PATTERN];
+
+    // Output
+    const MATCH: &'static str = "match";
+    const OUTPUT: [&'static str; 1] = [Regex::MATCH];
+
+    // Checks the group against the pattern up front, so a typo fails even
+    // when nothing matches.
+    fn group<'a>(pattern: &Pattern, raw: &'a str) -> Result<Group<'a>> {
+        let group = match raw {
+            "" => Group::Index(if pattern.captures_len() > 1 { 1 } else { 0 }),
+            raw => match raw.parse() {
+                Ok(index) if index < pattern.captures_len() => Group::Index(index),
+                Ok(_) => return Err(anyhow!("Pattern {} has no group {}.", pattern, raw)),
+                Err(_) if pattern.capture_names().flatten().any(|name| name == raw) => {
+                    Group::Name(raw)
+                }
+                Err(_) => return Err(anyhow!("Pattern {} has no group {}.", pattern, raw)),
+            },
+        };
+        Ok(group)
+    }
+}
+
+enum Group<'a> {
+    Index(usize),
+    Name(&'a str),
+}
+
+impl Group<'_> {
+    // A group that took no part in the match is empty.
+    fn take<'t>(&self, captures: &Captures<'t>) -> &'t str {
+        let group = match self {
+            Group::Index(index) => captures.get(*index),
+            Group::Name(name) => captures.name(name),
+        };
+        group.map_or("", |group| group.as_str())
+    }
+}
+
+impl Workflow for Regex {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let text = input.parameter(Regex::INPUT);
+        let raw = input.parameter(Regex::PATTERN);
+        let pattern = Pattern::new(raw).with_context(|| format!("Invalid pattern {}.", raw))?;
+        let group = Regex::group(&pattern, input.parameter(Regex::GROUP))?;
+        let all = input.flag(Regex::ALL, false)?;
+        let missing_ok = input.flag(Regex::MISSING_OK, false)?;
+
+        let matches: Vec<_> = pattern
+            .captures_iter(text)
+            .take(if all { usize::MAX } else { 1 })
+            .map(|captures| group.take(&captures))
+            .collect();
+        if matches.is_empty() && !missing_ok {
+            return Err(anyhow!("Pattern {} does not match the input.", raw));
+        }
+
+        let mut result = HashMap::new();
+        result.insert(Regex::MATCH.to_string(), matches.join("\n"));
+
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Regex::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Regex::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Regex::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run;
+
+    const OUTPUT: &str = "workflows 0.2.0 (built 2024-05-01)\nregex 1.4.6";
+
+    fn matched(parameters: &[(&'static str, &str)]) -> String {
+        let mut input = vec![(Regex::INPUT, OUTPUT)];
+        input.extend_from_slice(parameters);
+        run(Regex {}, &input).unwrap()[0][Regex::MATCH].clone()
+    }
+
+    #[test]
+    fn test_group() {
+        assert_eq!(matched(&[(Regex::PATTERN, r"\d+\.\d+\.\d+")]), "0.2.0");
+        assert_eq!(matched(&[(Regex::PATTERN, r"(\d+)\.(\d+)")]), "0");
+        assert_eq!(
+            matched(&[(Regex::PATTERN, r"(\d+)\.(\d+)"), (Regex::GROUP, "2")]),
+            "2"
+        );
+        assert_eq!(
+            matched(&[
+                (Regex::PATTERN, r"built (?P<date>[\d-]+)"),
+                (Regex::GROUP, "date")
+            ]),
+            "2024-05-01"
+        );
+        assert_eq!(
+            matched(&[(Regex::PATTERN, r"(?m)^\w+ ([\d.]+)"), (Regex::ALL, "true")]),
+            "0.2.0\n1.4.6"
+        );
+        assert_eq!(
+            run(
+                Regex {},
+                &[
+                    (Regex::INPUT, OUTPUT),
+                    (Regex::PATTERN, "(a)"),
+                    (Regex::GROUP, "v")
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            "Pattern (a) has no group v."
+        );
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert_eq!(
+            run(
+                Regex {},
+                &[(Regex::INPUT, OUTPUT), (Regex::PATTERN, "v\\d")]
+            )
+            .unwrap_err()
+            .to_string(),
+            "Pattern v\\d does not match the input."
+        );
+        assert_eq!(
+            matched(&[(Regex::PATTERN, "v\\d"), (Regex::MISSING_OK, "true")]),
+            ""
+        );
+        assert_eq!(
+            run(Regex {}, &[(Regex::INPUT, OUTPUT), (Regex::PATTERN, "(")])
+                .unwrap_err()
+                .to_string(),
+            "Invalid pattern (."
+        );
+    }
+}
@@ -11,6 +11,7 @@ impl Rss {
     const TEXT: &'static str = "text";
     const SCHEDULE_IN_SECS: &'static str = "schedule_in_secs";
     const PARAMS: [&'static str; 2] = [Rss::TEXT, Rss::SCHEDULE_IN_SECS];
+    const REQUIRED: [&'static str; 1] = [Rss::TEXT];
 
     // Output
     const TITLE: &'static str = "title";
@@ -19,46 +20,45 @@ impl Rss {
 }
 
 impl Workflow for Rss {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let text = input.parameter(Rss::TEXT);
         let after = input
             .parameter(Rss::SCHEDULE_IN_SECS)
             .parse()
             .map(|secs| Local::now() - Duration::seconds(secs));
 
-        let next = match context.next() {
-            Some(next) => next,
-            None => return Ok(()),
-        };
         let channel = Channel::read_from(BufReader::new(text.as_bytes()))?;
+        let mut outputs = Vec::new();
         for item in channel.items() {
-            if let (Ok(after), Some(Ok(pub_date))) = (
-                &after,
-                item.pub_date().map(|s| DateTime::parse_from_rfc2822(s)),
-            ) {
+            if let (Ok(after), Some(Ok(pub_date))) =
+                (&after, item.pub_date().map(DateTime::parse_from_rfc2822))
+            {
                 if &pub_date < after {
                     break;
                 }
             }
             let mut output = Outputs::new();
             output.insert(
-                Rss::TITLE,
+                Rss::TITLE.to_string(),
                 item.title().map(str::to_string).unwrap_or_default(),
             );
             output.insert(
-                Rss::LINK,
+                Rss::LINK.to_string(),
                 item.link().map(str::to_string).unwrap_or_default(),
             );
-            next.execute(context, output)?;
+            outputs.push(output);
         }
 
-        Ok(())
+        Ok(outputs)
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Rss::PARAMS;
+        &Rss::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Rss::REQUIRED
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Rss::OUTPUT;
+        &Rss::OUTPUT
     }
 }
@@ -1,7 +1,8 @@
-use crate::{Context, Input, Inputs, Workflow};
-use anyhow::Result;
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{Context as _, Result};
 use std::io::Write;
-use std::{collections::HashMap, fs::File};
+use std::path::Path;
+use std::{collections::HashMap, fs, fs::OpenOptions};
 
 pub struct Save {}
 
@@ -9,29 +10,86 @@ impl Save {
     // Input
     const TEXT: &'static str = "text";
     const DESTINATION: &'static str = "destination";
-    const PARAMS: [&'static str; 2] = [Save::TEXT, Save::DESTINATION];
+    // The names FileWrite steps use for text and destination.
+    const CONTENT: &'static str = "content";
+    const PATH: &'static str = "path";
+    // Adds to the end of the file rather than replacing it.
+    const APPEND: &'static str = "append";
+    const PARAMS: [&'static str; 5] = [
+        Save::TEXT,
+        Save::DESTINATION,
+        Save::CONTENT,
+        Save::PATH,
+        Save::APPEND,
+    ];
 
-    const OUTPUT: [&'static str; 0] = [];
+    // Output
+    // The file written, whichever parameter named it.
+    const OUTPUT: [&'static str; 1] = [Save::PATH];
 }
 
 impl Workflow for Save {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
-        let text = input.parameter(Save::TEXT);
-        let destination = input.parameter(Save::DESTINATION);
+    fn execute(&self, _context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let text = match input.parameter(Save::CONTENT) {
+            "" => input.parameter(Save::TEXT),
+            content => content,
+        };
+        let destination = match input.parameter(Save::PATH) {
+            "" => input.parameter(Save::DESTINATION),
+            path => path,
+        };
+        let append = input.flag(Save::APPEND, false)?;
 
-        let mut file = File::create(destination)?;
+        if let Some(parent) = Path::new(destination).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(destination)
+            .with_context(|| format!("Unable to open {}.", destination))?;
         file.write_all(text.as_bytes())?;
 
-        if let Some(next) = context.next() {
-            next.execute(context, HashMap::new())?;
-        }
-        Ok(())
+        let mut result = HashMap::new();
+        result.insert(Save::PATH.to_string(), destination.to_string());
+        Ok(vec![result])
     }
 
     fn parameters(&self) -> &'static [&'static str] {
-        return &Save::PARAMS;
+        &Save::PARAMS
     }
     fn outputs(&self) -> &'static [&'static str] {
-        return &Save::OUTPUT;
+        &Save::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run, scratch};
+
+    #[test]
+    fn test_append() {
+        let dir = scratch("save");
+        let path = dir.join("out/response.json");
+        let path = path.to_str().unwrap();
+
+        let outputs = run(Save {}, &[(Save::PATH, path), (Save::CONTENT, "a")]).unwrap();
+        assert_eq!(outputs[0][Save::PATH], path);
+        run(
+            Save {},
+            &[
+                (Save::PATH, path),
+                (Save::CONTENT, "b"),
+                (Save::APPEND, "true"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "ab");
+
+        run(Save {}, &[(Save::DESTINATION, path), (Save::TEXT, "c")]).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "c");
     }
 }
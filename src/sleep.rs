@@ -0,0 +1,31 @@
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::Result;
+use std::{thread, time::Duration};
+
+pub struct Sleep {}
+
+impl Sleep {
+    // Input
+    const DURATION_MS: &'static str = "duration_ms";
+    const PARAMS: [&'static str; 1] = [Sleep::DURATION_MS];
+
+    const OUTPUT: [&'static str; 0] = [];
+}
+
+impl Workflow for Sleep {
+    // Hands the previous step's outputs on untouched, so a poll can follow.
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let duration_ms = input.parse(Sleep::DURATION_MS)?.unwrap_or(0);
+
+        thread::sleep(Duration::from_millis(duration_ms));
+
+        Ok(vec![context.input.clone()])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Sleep::PARAMS
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Sleep::OUTPUT
+    }
+}
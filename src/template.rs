@@ -0,0 +1,112 @@
+use crate::parser::fulfill;
+use crate::{Context, Input, Inputs, Outputs, Workflow};
+use anyhow::{Context as _, Result};
+use std::path::Path;
+use std::{collections::HashMap, fs};
+
+pub struct Template {}
+
+impl Template {
+    // Input
+    // Filled against the previous step's outputs, like any step parameter.
+    const TEMPLATE: &'static str = "template";
+    // Read and filled the same way, for files too long to inline.
+    const TEMPLATE_FILE: &'static str = "template_file";
+    const OUTPUT_FILE: &'static str = "output_file";
+    const PARAMS: [&'static str; 3] = [
+        Template::TEMPLATE,
+        Template::TEMPLATE_FILE,
+        Template::OUTPUT_FILE,
+    ];
+
+    // Output
+    const TEXT: &'static str = "text";
+    const OUTPUT: [&'static str; 2] = [Template::TEXT, Template::OUTPUT_FILE];
+}
+
+impl Workflow for Template {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let template_file = input.parameter(Template::TEMPLATE_FILE);
+        let output_file = input.parameter(Template::OUTPUT_FILE);
+
+        // The inline template was filled along with the other parameters.
+        let text = match template_file {
+            "" => input.parameter(Template::TEMPLATE).to_string(),
+            template_file => {
+                let raw = fs::read_to_string(template_file)
+                    .with_context(|| format!("Unable to read {}.", template_file))?;
+                fulfill(&raw, &context.input, context)
+                    .with_context(|| format!("Unable to fill {}.", template_file))?
+            }
+        };
+
+        if !output_file.is_empty() {
+            if let Some(parent) = Path::new(output_file).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(output_file, &text)
+                .with_context(|| format!("Unable to write {}.", output_file))?;
+        }
+
+        let mut result = HashMap::new();
+        result.insert(Template::TEXT.to_string(), text);
+        result.insert(Template::OUTPUT_FILE.to_string(), output_file.to_string());
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Template::PARAMS
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Template::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{context_from, run_in, scratch};
+
+    fn execute(config: &str, parameters: &[(&'static str, &str)]) -> Result<Vec<Outputs>> {
+        let mut context = context_from(config);
+        context
+            .input
+            .insert("host".to_string(), "example.com".to_string());
+        run_in(&mut context, Template {}, parameters)
+    }
+
+    #[test]
+    fn test_template_file() {
+        let dir = scratch("template");
+        let template_file = dir.join("nginx.conf.tmpl");
+        fs::write(
+            &template_file,
+            "server_name {input.host};\nroot {input.root};\n",
+        )
+        .unwrap();
+        let template_file = template_file.to_str().unwrap();
+        let output_file = dir.join("conf/nginx.conf");
+        let output_file = output_file.to_str().unwrap();
+
+        let error =
+            execute("workflows: []", &[(Template::TEMPLATE_FILE, template_file)]).unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "Missing root.");
+
+        let outputs = execute(
+            "{strict_templates: false, workflows: []}",
+            &[
+                (Template::TEMPLATE_FILE, template_file),
+                (Template::OUTPUT_FILE, output_file),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            outputs[0][Template::TEXT],
+            "server_name example.com;\nroot ;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(output_file).unwrap(),
+            outputs[0][Template::TEXT]
+        );
+    }
+}
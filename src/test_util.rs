@@ -0,0 +1,114 @@
+// Helpers shared by the tests of the workflows.
+use crate::{Context, Outputs, Workflow};
+use anyhow::Result;
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+// A context for a config without steps.
+pub fn context() -> Context {
+    context_from("workflows: []")
+}
+
+pub fn context_from(config: &str) -> Context {
+    Context::new(serde_yaml::from_str(config).unwrap())
+}
+
+// A fresh, empty directory under the system's temporary one, named for the
+// test and the process running it.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("workflows-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Runs a workflow with the given parameters, already filled.
+pub fn run(workflow: impl Workflow, parameters: &[(&'static str, &str)]) -> Result<Vec<Outputs>> {
+    run_in(&mut context(), workflow, parameters)
+}
+
+pub fn run_in(
+    context: &mut Context,
+    workflow: impl Workflow,
+    parameters: &[(&'static str, &str)],
+) -> Result<Vec<Outputs>> {
+    let input = parameters
+        .iter()
+        .map(|(key, value)| (*key, value.to_string()))
+        .collect();
+    workflow.execute(context, input)
+}
+
+// Answers each connection with what `respond` returns for its request, and
+// hands every request back. Returns the server's `http://` address.
+pub fn serve(
+    mut respond: impl FnMut(&str) -> String + Send + 'static,
+) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let request = match read_request(&mut stream) {
+                Some(request) => request,
+                None => continue,
+            };
+            let _ = stream.write_all(respond(&request).as_bytes());
+            let _ = sender.send(request);
+        }
+    });
+    (address, receiver)
+}
+
+// Answers with responses in turn, one per connection, then with a 500.
+pub fn serve_all<S: Into<String>>(responses: Vec<S>) -> (String, Receiver<String>) {
+    let mut responses = responses
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<String>>()
+        .into_iter();
+    serve(move |_| {
+        responses
+            .next()
+            .unwrap_or_else(|| response("500 Internal Server Error", ""))
+    })
+}
+
+pub fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// Reads a request up to the end of its Content-Length body, or `None` when
+// the connection closes first.
+pub fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, value)| value.trim().parse().unwrap());
+            if body.len() >= length {
+                return Some(text.into_owned());
+            }
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => return None,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use anyhow::{anyhow, Context as _, Result};
 use serde::Serializer;
+use serde_json::Value;
 
 // Serde crate enforces following signature.
 #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -8,3 +10,209 @@ where
 {
     serializer.serialize_u64(if *input { 1 } else { 0 })
 }
+
+// Parses true/1/yes/on and false/0/no/off in any case, rejecting anything else.
+pub fn boolean(raw: &str) -> Result<bool> {
+    match raw.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(anyhow!("Expected true or false, but got {}.", raw)),
+    }
+}
+
+// Parses one `key<delimiter>value` pair per line, skipping blank lines.
+pub fn pairs(raw: &str, delimiter: char) -> Result<Vec<(&str, &str)>> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split_once(delimiter)
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| anyhow!("Expected {} in {}.", delimiter, line))
+        })
+        .collect()
+}
+
+// Walks a dotted path such as `data.items.0.id` through objects and arrays.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Result<&'a Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |value, segment| {
+            match value {
+                Value::Object(map) => map.get(segment),
+                Value::Array(items) => segment.parse().ok().and_then(|i: usize| items.get(i)),
+                _ => None,
+            }
+            .with_context(|| format!("Missing {} in path {}.", segment, path))
+        })
+}
+
+// Strings are rendered without quotes, everything else as JSON.
+pub fn render(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Parses a .env file: `KEY=VALUE` lines with an optional `export` prefix and
+// optionally quoted values. Blank lines and `#` comments are skipped.
+pub fn dotenv(raw: &str) -> Result<Vec<(&str, &str)>> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected = in {}.", line))?;
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                .unwrap_or(value);
+            Ok((key.trim(), value))
+        })
+        .collect()
+}
+
+// Hides values whose names look like credentials before they are shown.
+pub fn mask<'a>(key: &str, value: &'a str) -> &'a str {
+    // Webhook urls carry their secret in the path.
+    const SENSITIVE: [&str; 7] = [
+        "TOKEN", "SECRET", "PASSWORD", "PASS", "KEY", "AUTH", "WEBHOOK",
+    ];
+    let key = key.to_uppercase();
+    if !value.is_empty() && SENSITIVE.iter().any(|word| key.contains(word)) {
+        "***"
+    } else {
+        value
+    }
+}
+
+// Splits a command line on whitespace, honouring single quotes, double quotes and
+// backslash escapes outside single quotes. Only quotes, backslashes and
+// whitespace can be escaped, so Windows paths keep their backslashes.
+pub fn split_args(raw: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote = None;
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => current.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let escaped = chars
+                    .next_if(|&next| matches!(next, '"' | '\'' | '\\') || next.is_whitespace())
+                    .unwrap_or('\\');
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => current.get_or_insert_with(String::new).push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(current.take()),
+            (None, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(anyhow!("Unterminated {} in arguments.", q));
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boolean() {
+        for raw in ["true", "1", "Yes", " on "] {
+            assert!(boolean(raw).unwrap(), "{}", raw);
+        }
+        for raw in ["false", "0", "NO", "off"] {
+            assert!(!boolean(raw).unwrap(), "{}", raw);
+        }
+        assert_eq!(
+            boolean("ture").unwrap_err().to_string(),
+            "Expected true or false, but got ture."
+        );
+    }
+
+    #[test]
+    fn test_pairs() {
+        assert_eq!(
+            pairs("Content-Type: application/json\n\n Accept : */*", ':').unwrap(),
+            vec![("Content-Type", "application/json"), ("Accept", "*/*")]
+        );
+        assert_eq!(pairs("a=b=c", '=').unwrap(), vec![("a", "b=c")]);
+        assert!(pairs("no delimiter", ':').is_err());
+        assert!(pairs("", ':').unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dotenv() {
+        let raw = "# secrets\n\nTOKEN=abc\nexport NAME = \"Ann Lee\"\nEMPTY=\nQUOTE='a=b'";
+        assert_eq!(
+            dotenv(raw).unwrap(),
+            vec![
+                ("TOKEN", "abc"),
+                ("NAME", "Ann Lee"),
+                ("EMPTY", ""),
+                ("QUOTE", "a=b")
+            ]
+        );
+        assert!(dotenv("NOVALUE").is_err());
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("GITHUB_TOKEN", "ghp_123"), "***");
+        assert_eq!(mask("basic_auth_pass", "hunter2"), "***");
+        assert_eq!(mask("api_key", ""), "");
+        assert_eq!(mask("webhook_url", "https://hooks.slack.com/T/B/x"), "***");
+        assert_eq!(mask("status_code", "200"), "200");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let json = serde_json::json!({"data": {"items": [{"id": 7}, {"id": "x"}]}});
+        assert_eq!(render(lookup(&json, "data.items.0.id").unwrap()), "7");
+        assert_eq!(render(lookup(&json, "data.items.1.id").unwrap()), "x");
+        assert_eq!(
+            render(lookup(&json, "data.items.0").unwrap()),
+            r#"{"id":7}"#
+        );
+        assert_eq!(lookup(&json, "").unwrap(), &json);
+        assert_eq!(
+            lookup(&json, "data.items.2.id").unwrap_err().to_string(),
+            "Missing 2 in path data.items.2.id."
+        );
+        assert!(lookup(&json, "data.missing").is_err());
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"-c "echo 'hi there'"  'a "b"' c\ d """#).unwrap(),
+            vec!["-c", "echo 'hi there'", r#"a "b""#, "c d", ""]
+        );
+        assert_eq!(
+            split_args(r#"C:\tools\x.exe "C:\Program Files\app" \\server\share\"#).unwrap(),
+            vec![
+                r"C:\tools\x.exe",
+                r"C:\Program Files\app",
+                r"\server\share\"
+            ]
+        );
+        assert_eq!(
+            split_args(r#""say 'hi \"you\"'" 'it"s' \'quoted\'"#).unwrap(),
+            vec![r#"say 'hi "you"'"#, r#"it"s"#, "'quoted'"]
+        );
+        assert!(split_args("").unwrap().is_empty());
+        assert!(split_args(r#""unterminated"#).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    http::{ClientConfig, Http},
+    util::render,
+    Context, Input, Inputs, Outputs, Workflow,
+};
+use anyhow::{anyhow, Context as _, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+pub struct Webhook {}
+
+impl Webhook {
+    // Input
+    const WEBHOOK_URL: &'static str = "webhook_url";
+    // The message sent, also accepted as text.
+    const MESSAGE: &'static str = "message";
+    const TEXT: &'static str = "text";
+    // The payload field holding the message: `text` (default) suits Slack and
+    // Teams, `content` suits Discord.
+    const TEXT_FIELD: &'static str = "text_field";
+    // A JSON object to send, with the message added under text_field if any.
+    const PAYLOAD: &'static str = "payload";
+    const PROXY: &'static str = "proxy";
+    // 30 seconds unless set, as with Http.
+    const TIMEOUT_SECS: &'static str = "timeout_secs";
+    const PARAMS: [&'static str; 7] = [
+        Webhook::WEBHOOK_URL,
+        Webhook::MESSAGE,
+        Webhook::TEXT,
+        Webhook::TEXT_FIELD,
+        Webhook::PAYLOAD,
+        Webhook::PROXY,
+        Webhook::TIMEOUT_SECS,
+    ];
+    const REQUIRED: [&'static str; 1] = [Webhook::WEBHOOK_URL];
+
+    // Output
+    const STATUS_CODE: &'static str = "status_code";
+    // The response body.
+    const OUTPUT: [&'static str; 2] = [Webhook::STATUS_CODE, Webhook::TEXT];
+
+    const DEFAULT_TEXT_FIELD: &'static str = "text";
+
+    fn payload(raw: &str, text_field: &str, message: &str) -> Result<Value> {
+        let mut payload = match raw {
+            "" => Map::new(),
+            raw => match serde_json::from_str(raw).context("Invalid payload.")? {
+                Value::Object(payload) => payload,
+                _ => return Err(anyhow!("Webhook payload must be a JSON object.")),
+            },
+        };
+        if !message.trim().is_empty() {
+            payload.insert(text_field.to_string(), Value::from(message));
+        }
+        if payload.is_empty() {
+            return Err(anyhow!("Webhook cannot send an empty message."));
+        }
+        Ok(Value::Object(payload))
+    }
+
+    // Slack's API answers 200 with `{"ok": false, "error": ...}` when it
+    // rejects a message.
+    fn rejection(body: &str) -> Option<String> {
+        let body: Value = serde_json::from_str(body).ok()?;
+        match body.get("ok") {
+            Some(Value::Bool(false)) => Some(body.get("error").map(render).unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+impl Workflow for Webhook {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
+        let url = input.parameter(Webhook::WEBHOOK_URL);
+        let message = match input.parameter(Webhook::MESSAGE) {
+            "" => input.parameter(Webhook::TEXT),
+            message => message,
+        };
+        let text_field = match input.parameter(Webhook::TEXT_FIELD) {
+            "" => Webhook::DEFAULT_TEXT_FIELD,
+            text_field => text_field,
+        };
+        let payload = Webhook::payload(input.parameter(Webhook::PAYLOAD), text_field, message)?;
+        let config = ClientConfig::new(
+            input.parameter(Webhook::PROXY),
+            input.parse(Webhook::TIMEOUT_SECS)?,
+        );
+
+        let client = Http::client(context, config)?;
+        // The url carries the webhook's secret, so keep it out of the errors.
+        let response = client
+            .post(url)
+            .json(&payload)
+            .send()
+            .map_err(reqwest::Error::without_url)
+            .context("Unable to reach the webhook.")?;
+        let status = response.status();
+        let body = response.text().map_err(reqwest::Error::without_url)?;
+        if !status.is_success() {
+            return Err(anyhow!("Webhook returned {}: {}", status, body.trim()));
+        }
+        if let Some(error) = Webhook::rejection(&body) {
+            return Err(anyhow!("Webhook rejected the message: {}", error));
+        }
+
+        let mut result = HashMap::new();
+        result.insert(
+            Webhook::STATUS_CODE.to_string(),
+            status.as_str().to_string(),
+        );
+        result.insert(Webhook::TEXT.to_string(), body);
+
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &Webhook::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &Webhook::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &Webhook::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, run, serve_all};
+    use std::sync::mpsc::Receiver;
+
+    // Serves one response, returning the webhook url and the received payload.
+    fn serve(status: &str, body: &str) -> (String, Receiver<String>) {
+        let (address, requests) = serve_all(vec![response(status, body)]);
+        (format!("{}/hook", address), requests)
+    }
+
+    fn payload(requests: &Receiver<String>) -> String {
+        let request = requests.recv().unwrap();
+        request.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn test_send() {
+        let (url, requests) = serve("200 OK", "ok");
+        let outputs = run(
+            Webhook {},
+            &[(Webhook::WEBHOOK_URL, &url), (Webhook::TEXT, "Deployed")],
+        )
+        .unwrap();
+        assert_eq!(outputs[0][Webhook::STATUS_CODE], "200");
+        assert_eq!(outputs[0][Webhook::TEXT], "ok");
+        assert_eq!(payload(&requests), r#"{"text":"Deployed"}"#);
+
+        let (url, requests) = serve("204 No Content", "");
+        run(
+            Webhook {},
+            &[
+                (Webhook::WEBHOOK_URL, &url),
+                (Webhook::MESSAGE, "Deployed"),
+                (Webhook::TEXT_FIELD, "content"),
+                (Webhook::PAYLOAD, r#"{"username": "ci"}"#),
+            ],
+        )
+        .unwrap();
+        let payload: Value = serde_json::from_str(&payload(&requests)).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({"username": "ci", "content": "Deployed"})
+        );
+    }
+
+    #[test]
+    fn test_failure() {
+        let (url, _) = serve("404 Not Found", "no_service\n");
+        assert_eq!(
+            run(
+                Webhook {},
+                &[(Webhook::WEBHOOK_URL, &url), (Webhook::TEXT, "hi")]
+            )
+            .unwrap_err()
+            .to_string(),
+            "Webhook returned 404 Not Found: no_service"
+        );
+
+        let (url, _) = serve("200 OK", r#"{"ok": false, "error": "channel_not_found"}"#);
+        assert_eq!(
+            run(
+                Webhook {},
+                &[(Webhook::WEBHOOK_URL, &url), (Webhook::TEXT, "hi")]
+            )
+            .unwrap_err()
+            .to_string(),
+            "Webhook rejected the message: channel_not_found"
+        );
+
+        let url = "http://127.0.0.1:1/services/T000/B000/secret";
+        let error = run(
+            Webhook {},
+            &[(Webhook::WEBHOOK_URL, url), (Webhook::TEXT, "hi")],
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Unable to reach the webhook.");
+        assert!(!format!("{:?}", error).contains("secret"));
+
+        assert_eq!(
+            run(Webhook {}, &[(Webhook::WEBHOOK_URL, "http://127.0.0.1:1")])
+                .unwrap_err()
+                .to_string(),
+            "Webhook cannot send an empty message."
+        );
+        assert_eq!(
+            run(
+                Webhook {},
+                &[
+                    (Webhook::WEBHOOK_URL, "http://127.0.0.1:1"),
+                    (Webhook::PAYLOAD, "[]")
+                ]
+            )
+            .unwrap_err()
+            .to_string(),
+            "Webhook payload must be a JSON object."
+        );
+    }
+}
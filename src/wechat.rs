@@ -1,17 +1,26 @@
-use crate::{Context, Input, Inputs, Workflow};
+use crate::{
+    http::{ClientConfig, Http},
+    Context, Input, Inputs, Outputs, Workflow,
+};
 
-use anyhow::Result;
-use reqwest::blocking::Client;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use anyhow::{anyhow, Result};
+use log::warn;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, thread, time::Duration};
+use strum::EnumString;
 
+// Only a successful response carries the token.
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct WeChatAccessToken {
     #[serde(rename = "errcode")]
-    error_code: u64,
+    error_code: i64,
     #[serde(rename = "errmsg")]
     error_message: String,
+    #[serde(default)]
     access_token: String,
+    #[serde(default)]
     expires_in: u64,
 }
 
@@ -24,14 +33,49 @@ struct WeChatMessage<'a> {
     #[serde(rename = "agentid")]
     agent_id: i64,
     #[serde(rename = "msgtype")]
-    message_type: &'a str,
-    text: WeChatMessageText<'a>,
+    message_type: MessageType,
+    // Only the field named by message_type is sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<WeChatMessageText<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    markdown: Option<WeChatMessageText<'a>>,
     #[serde(serialize_with = "crate::util::bool_to_int")]
     enable_duplicate_check: bool,
     duplicate_check_interval: u64,
 }
 
-#[derive(Debug, Serialize)]
+impl<'a> WeChatMessage<'a> {
+    fn new(
+        to_user: &'a str,
+        agent_id: i64,
+        message_type: MessageType,
+        content: &'a str,
+    ) -> WeChatMessage<'a> {
+        let content = Some(WeChatMessageText { content });
+        WeChatMessage {
+            to_user,
+            to_party: None,
+            agent_id,
+            message_type,
+            text: content
+                .clone()
+                .filter(|_| message_type == MessageType::Text),
+            markdown: content.filter(|_| message_type == MessageType::Markdown),
+            enable_duplicate_check: false,
+            duplicate_check_interval: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+enum MessageType {
+    Text,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Serialize)]
 struct WeChatMessageText<'a> {
     content: &'a str,
 }
@@ -39,9 +83,11 @@ struct WeChatMessageText<'a> {
 #[derive(Debug, Deserialize)]
 struct WeChatSendResponse {
     #[serde(rename = "errcode")]
-    error_code: u64,
+    error_code: i64,
     #[serde(rename = "errmsg")]
     error_message: String,
+    #[serde(rename = "msgid", default)]
+    message_id: String,
 }
 
 pub struct WeChat {}
@@ -51,65 +97,276 @@ impl WeChat {
     const CORP_ID: &'static str = "corp_id";
     const CORP_SECRET: &'static str = "secret";
     const AGENT_ID: &'static str = "agent_id";
+    // The message sent, also accepted as text.
+    const MESSAGE: &'static str = "message";
     const TEXT: &'static str = "text";
-    const PARAMS: [&'static str; 4] = [
+    // Comma-separated user ids, everyone in the app by default.
+    const TO: &'static str = "to";
+    // `text` (default) or `markdown`, which renders message as a card.
+    const MESSAGE_TYPE: &'static str = "msgtype";
+    // Sends again after network failures and while WeChat reports itself busy.
+    const RETRIES: &'static str = "retries";
+    const RETRY_BACKOFF_MS: &'static str = "retry_backoff_ms";
+    const PARAMS: [&'static str; 9] = [
         WeChat::CORP_ID,
         WeChat::CORP_SECRET,
         WeChat::AGENT_ID,
+        WeChat::MESSAGE,
         WeChat::TEXT,
+        WeChat::TO,
+        WeChat::MESSAGE_TYPE,
+        WeChat::RETRIES,
+        WeChat::RETRY_BACKOFF_MS,
     ];
+    const REQUIRED: [&'static str; 3] = [WeChat::CORP_ID, WeChat::CORP_SECRET, WeChat::AGENT_ID];
 
     // Output
     const ERROR_CODE: &'static str = "error_code";
-    const OUTPUT: [&'static str; 0] = [];
+    const MESSAGE_ID: &'static str = "message_id";
+    const OUTPUT: [&'static str; 2] = [WeChat::ERROR_CODE, WeChat::MESSAGE_ID];
+
+    const API: &'static str = "https://qyapi.weixin.qq.com/cgi-bin";
+    const DEFAULT_RETRY_BACKOFF_MS: u64 = 1000;
+    // The errcode for "system busy, try again".
+    const BUSY: i64 = -1;
+
+    const ALL_USERS: &'static str = "@all";
+
+    // WeChat separates user ids with `|`.
+    fn recipients(to: &str) -> String {
+        let users: Vec<_> = to
+            .split(',')
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .collect();
+        match users[..] {
+            [] => WeChat::ALL_USERS.to_string(),
+            _ => users.join("|"),
+        }
+    }
+}
+
+impl WeChat {
+    // Fetches a token and sends the message with it. Either response's
+    // errcode comes back as is.
+    fn send(
+        client: &Client,
+        api: &str,
+        corp_id: &str,
+        secret: &str,
+        message: &WeChatMessage,
+    ) -> Result<WeChatSendResponse> {
+        let token: WeChatAccessToken = WeChat::request(
+            client
+                .get(format!("{}/gettoken", api))
+                .query(&[("corpid", corp_id), ("corpsecret", secret)]),
+        )?;
+        if token.error_code != 0 {
+            return Ok(WeChatSendResponse {
+                error_code: token.error_code,
+                error_message: token.error_message,
+                message_id: String::new(),
+            });
+        }
+        WeChat::request(
+            client
+                .post(format!("{}/message/send", api))
+                .query(&[("access_token", &token.access_token)])
+                .json(message),
+        )
+    }
+
+    // The query carries the secret or the token, so errors leave out the url.
+    fn request<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+        Ok(request
+            .send()
+            .and_then(Response::error_for_status)
+            .and_then(Response::json)
+            .map_err(reqwest::Error::without_url)?)
+    }
+
+    // Backs off exponentially between attempts, like Http.
+    fn send_with_retries(
+        send: impl Fn() -> Result<WeChatSendResponse>,
+        retries: u32,
+        backoff: u64,
+    ) -> Result<String> {
+        let mut attempt = 0;
+        let response = loop {
+            let response = send();
+            let transient = match &response {
+                Ok(response) => response.error_code == WeChat::BUSY,
+                Err(_) => true,
+            };
+            if !transient || attempt >= retries {
+                break response?;
+            }
+            warn!("WeChat failed to send the message, retrying.");
+            thread::sleep(Duration::from_millis(
+                backoff.saturating_mul(2u64.saturating_pow(attempt)),
+            ));
+            attempt += 1;
+        };
+        if response.error_code != 0 {
+            return Err(anyhow!(
+                "WeChat failed to send the message with {}: {}",
+                response.error_code,
+                response.error_message
+            ));
+        }
+        Ok(response.message_id)
+    }
 }
 
 impl Workflow for WeChat {
-    fn execute(&self, context: &mut Context, input: Inputs) -> Result<()> {
+    fn execute(&self, context: &mut Context, input: Inputs) -> Result<Vec<Outputs>> {
         let corp_id = input.parameter(WeChat::CORP_ID);
         let secret = input.parameter(WeChat::CORP_SECRET);
         let agent_id = input.parameter(WeChat::AGENT_ID).parse()?;
-        let text = input.parameter(WeChat::TEXT);
+        let text = match input.parameter(WeChat::MESSAGE) {
+            "" => input.parameter(WeChat::TEXT),
+            message => message,
+        };
+        let to_user = WeChat::recipients(input.parameter(WeChat::TO));
+        let message_type = input
+            .parse(WeChat::MESSAGE_TYPE)?
+            .unwrap_or(MessageType::Text);
+        if text.trim().is_empty() {
+            return Err(anyhow!("WeChat cannot send an empty message."));
+        }
 
-        let client = Client::new();
+        let retries = input.parse(WeChat::RETRIES)?.unwrap_or(0);
+        let backoff = input
+            .parse(WeChat::RETRY_BACKOFF_MS)?
+            .unwrap_or(WeChat::DEFAULT_RETRY_BACKOFF_MS);
 
-        let url = format!(
-            "https://qyapi.weixin.qq.com/cgi-bin/gettoken?corpid={}&corpsecret={}",
-            corp_id, secret
-        );
+        // The proxy comes from HTTP(S)_PROXY, as for Http steps without one.
+        let client = Http::client(context, ClientConfig::new("", None))?;
+        let message = WeChatMessage::new(&to_user, agent_id, message_type, text);
+        let message_id = WeChat::send_with_retries(
+            || WeChat::send(&client, WeChat::API, corp_id, secret, &message),
+            retries,
+            backoff,
+        )?;
 
-        let response = client.get(&url).send()?;
-        let token: WeChatAccessToken = response.json()?;
+        let mut result = HashMap::new();
+        result.insert(WeChat::ERROR_CODE.to_string(), "0".to_string());
+        result.insert(WeChat::MESSAGE_ID.to_string(), message_id);
 
-        let message = WeChatMessage {
-            to_user: "@all",
-            to_party: None,
-            agent_id: agent_id,
-            message_type: "text",
-            text: WeChatMessageText { content: text },
-            enable_duplicate_check: false,
-            duplicate_check_interval: 0,
-        };
+        Ok(vec![result])
+    }
+
+    fn parameters(&self) -> &'static [&'static str] {
+        &WeChat::PARAMS
+    }
+    fn required_parameters(&self) -> &'static [&'static str] {
+        &WeChat::REQUIRED
+    }
+    fn outputs(&self) -> &'static [&'static str] {
+        &WeChat::OUTPUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response, run, serve_all};
+    use serde_json::json;
+    use std::sync::mpsc::Receiver;
+
+    #[test]
+    fn test_recipients() {
+        assert_eq!(WeChat::recipients(""), "@all");
+        assert_eq!(WeChat::recipients("ann, bob,,"), "ann|bob");
+    }
 
-        let url = format!(
-            "https://qyapi.weixin.qq.com/cgi-bin/message/send?access_token={}",
-            token.access_token
+    #[test]
+    fn test_message_type() {
+        let message = WeChatMessage::new("@all", 2, MessageType::Markdown, "**done**");
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "touser": "@all",
+                "toparty": null,
+                "agentid": 2,
+                "msgtype": "markdown",
+                "markdown": {"content": "**done**"},
+                "enable_duplicate_check": 0,
+                "duplicate_check_interval": 0,
+            })
         );
-        let response: WeChatSendResponse = client.post(&url).json(&message).send()?.json()?;
+        let message = WeChatMessage::new("ann", 2, MessageType::Text, "done");
+        let message = serde_json::to_value(&message).unwrap();
+        assert_eq!(message["msgtype"], "text");
+        assert_eq!(message["text"]["content"], "done");
+        assert!(message.get("markdown").is_none());
+    }
 
-        let mut result = HashMap::new();
-        result.insert(WeChat::ERROR_CODE, response.error_code.to_string());
+    // Answers the token and send requests of each attempt in turn.
+    fn serve(bodies: &[&str]) -> (String, Receiver<String>) {
+        let responses = bodies.iter().map(|body| response("200 OK", body)).collect();
+        let (address, requests) = serve_all(responses);
+        (format!("{}/cgi-bin", address), requests)
+    }
 
-        if let Some(next) = context.next() {
-            next.execute(context, result)?;
-        }
-        Ok(())
+    fn send(api: &str, retries: u32) -> Result<String> {
+        let client = Client::new();
+        let message = WeChatMessage::new("@all", 2, MessageType::Text, "done");
+        WeChat::send_with_retries(
+            || WeChat::send(&client, api, "corp", "a&b+c#d", &message),
+            retries,
+            1,
+        )
     }
 
-    fn parameters(&self) -> &'static [&'static str] {
-        return &WeChat::PARAMS;
+    #[test]
+    fn test_send() {
+        const TOKEN: &str =
+            r#"{"errcode": 0, "errmsg": "ok", "access_token": "t", "expires_in": 7200}"#;
+        let (api, requests) = serve(&[
+            TOKEN,
+            r#"{"errcode": -1, "errmsg": "system busy"}"#,
+            TOKEN,
+            r#"{"errcode": 0, "errmsg": "ok", "msgid": "m1"}"#,
+        ]);
+        assert_eq!(send(&api, 1).unwrap(), "m1");
+        assert!(requests
+            .recv()
+            .unwrap()
+            .starts_with("GET /cgi-bin/gettoken?corpid=corp&corpsecret=a%26b%2Bc%23d HTTP/1.1"));
+        assert!(requests
+            .recv()
+            .unwrap()
+            .starts_with("POST /cgi-bin/message/send?access_token=t HTTP/1.1"));
+
+        let (api, _) = serve(&[r#"{"errcode": 40001, "errmsg": "invalid credential"}"#]);
+        assert_eq!(
+            send(&api, 3).unwrap_err().to_string(),
+            "WeChat failed to send the message with 40001: invalid credential"
+        );
     }
-    fn outputs(&self) -> &'static [&'static str] {
-        return &WeChat::OUTPUT;
+
+    #[test]
+    fn test_errors_hide_the_secret() {
+        let (api, _) = serve_all(vec![response("500 Internal Server Error", "")]);
+        let error = send(&format!("{}/cgi-bin", api), 0).unwrap_err();
+        assert!(error.to_string().contains("500 Internal Server Error"));
+        assert!(!format!("{:?}", error).contains("corpsecret"));
+
+        let error = send("http://127.0.0.1:1/cgi-bin", 0).unwrap_err();
+        assert!(!format!("{:?}", error).contains("corpsecret"));
+    }
+
+    #[test]
+    fn test_empty_message() {
+        assert_eq!(
+            run(
+                WeChat {},
+                &[(WeChat::AGENT_ID, "1"), (WeChat::MESSAGE, " ")]
+            )
+            .unwrap_err()
+            .to_string(),
+            "WeChat cannot send an empty message."
+        );
     }
 }